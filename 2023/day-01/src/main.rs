@@ -1,110 +1,160 @@
-use std::collections::HashMap;
-
 fn get_input() -> &'static str {
     include_str!("../input.txt")
 }
 
-fn part1(input: &str) -> String {
-    let answer: u32 = input
-        .lines()
-        .map(|line| {
-            let mut digits = line.chars().filter_map(|x| {
-                if x.is_ascii_digit() {
-                    Some(x.to_string().parse::<u32>().unwrap())
-                } else {
-                    None
+// A set of tokens that we recognise in a line, each with the value that it represents.
+// The radix is used when tokens are combined by concatenation.
+#[derive(Clone, Debug)]
+struct Alphabet {
+    tokens: Vec<(String, u64)>,
+    radix: u64,
+}
+
+impl Alphabet {
+    fn new<S: ToString>(tokens: &[(S, u64)], radix: u64) -> Alphabet {
+        Alphabet {
+            tokens: tokens.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+            radix,
+        }
+    }
+
+    // The ASCII digits '0' to '9'.
+    fn decimal() -> Alphabet {
+        let tokens: Vec<_> = (0..10).map(|i| (i.to_string(), i)).collect();
+        Alphabet::new(&tokens, 10)
+    }
+
+    // The ASCII digits '0' to '9', and then 'a' to 'f'.
+    #[cfg_attr(not(test), expect(dead_code))]
+    fn hex() -> Alphabet {
+        let tokens: Vec<_> = (0..16).map(|i| (format!("{i:x}"), i)).collect();
+        Alphabet::new(&tokens, 16)
+    }
+
+    // The spelled-out digits "one" to "nine".
+    fn english_words() -> Alphabet {
+        let words = [
+            "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+        ];
+        let tokens: Vec<_> = (1..).zip(words).map(|(i, word)| (word, i)).collect();
+        Alphabet::new(&tokens, 10)
+    }
+
+    // The individual Roman numerals 'I' to 'M'.
+    #[cfg_attr(not(test), expect(dead_code))]
+    fn roman() -> Alphabet {
+        Alphabet::new(
+            &[
+                ("I", 1),
+                ("V", 5),
+                ("X", 10),
+                ("L", 50),
+                ("C", 100),
+                ("D", 500),
+                ("M", 1000),
+            ],
+            10,
+        )
+    }
+
+    // Recognise tokens from either alphabet; the radix of `self` is kept.
+    fn with(mut self, other: &Alphabet) -> Alphabet {
+        self.tokens.extend_from_slice(&other.tokens);
+        self
+    }
+
+    // Return the values of all tokens found in `line`, in the order in which they start.
+    // NOTE: Overlapping tokens are all found, e.g. "eightwo" gives [8, 2]. This is
+    //  required to match the behaviour of the puzzle.
+    fn find_values(&self, line: &str) -> Vec<u64> {
+        line.char_indices()
+            .filter_map(|(i, _)| {
+                let rest = &line[i..];
+                self.tokens
+                    .iter()
+                    .find(|(token, _)| rest.starts_with(token.as_str()))
+                    .map(|&(_, value)| value)
+            })
+            .collect()
+    }
+}
+
+// How the selected tokens should be turned into a single value.
+#[derive(Clone, Copy, Debug)]
+enum Combine {
+    // Write the values one after the other in the alphabet's radix, e.g. [1, 2] -> 12.
+    Concatenate,
+    #[cfg_attr(not(test), expect(dead_code))]
+    Sum,
+    #[cfg_attr(not(test), expect(dead_code))]
+    Product,
+}
+
+impl Combine {
+    // Returns `None` on overflow.
+    fn apply(self, radix: u64, values: &[u64]) -> Option<u64> {
+        match self {
+            Combine::Concatenate => values.iter().try_fold(0u64, |acc, &value| {
+                // Shift far enough to fit all digits of `value`; always at least one digit.
+                let mut shift = radix;
+                while shift <= value {
+                    shift = shift.checked_mul(radix)?;
                 }
-            });
-            let first_digit = digits.next().unwrap();
-            let last_digit = match digits.next_back() {
-                Some(x) => x,
-                None => first_digit,
-            };
-            first_digit * 10 + last_digit
-        })
-        .sum();
-
-    answer.to_string()
+                acc.checked_mul(shift)?.checked_add(value)
+            }),
+            Combine::Sum => values.iter().try_fold(0u64, |acc, &x| acc.checked_add(x)),
+            Combine::Product => values.iter().try_fold(1u64, |acc, &x| acc.checked_mul(x)),
+        }
+    }
+}
+
+// Extract a calibration value from a line by taking the first `n` and the last `n` tokens
+// found, and combining them.
+// NOTE: The two groups may overlap, e.g. with `n = 1` a line containing a single digit
+//  uses that digit as both the first and the last token.
+#[derive(Clone, Debug)]
+struct CalibrationRule {
+    alphabet: Alphabet,
+    n: usize,
+    combine: Combine,
 }
 
-// Replace any occurrences of known digits with digits.
-// NOTE: We always replace ALL digits, even overlapping. To rationalise this,
-//  we only replace the first character of the number with the digit, e.g.
-//      "eightwo" -> "8igh2wo"
-//  This means that the resulting string has the same length as the input.
-fn replace_string_numbers(line: &str) -> String {
-    // Find all the literal numbers in the string.
-    let digit_to_locations = find_literal_digit_occurrences(line);
-
-    // NOTE: For future learning...
-    // Initially I came up with the following contorted code:
-    //
-    //      let line_new: Vec<u8> = line.as_bytes().into_iter().map(|&x| x).collect();
-    //
-    // What's happening here is that `as_bytes` is referring to (immutable) bytes
-    // stored inside `line`. So the weird `map` call is required to actually do a copy.
-    //
-    // The below is better -- here we do the copy in one go with `to_string`, and then
-    // use `into_bytes` to consume the String and get a Vec<u8> (which is owning).
-    let mut line_bytes = line.to_string().into_bytes();
-
-    for (digit, locations) in digit_to_locations {
-        for loc in locations {
-            line_bytes[loc] = digit;
+impl CalibrationRule {
+    // Returns `None` if the line has fewer than `n` tokens, or the value overflows.
+    fn value(&self, line: &str) -> Option<u64> {
+        let values = self.alphabet.find_values(line);
+        if values.len() < self.n {
+            return None;
         }
+        let selected: Vec<u64> = values[..self.n]
+            .iter()
+            .chain(&values[(values.len() - self.n)..])
+            .copied()
+            .collect();
+        self.combine.apply(self.alphabet.radix, &selected)
     }
 
-    String::from_utf8(line_bytes).unwrap()
+    fn total(&self, input: &str) -> u64 {
+        input.lines().map(|line| self.value(line).unwrap()).sum()
+    }
 }
 
-// Return a map from digit (as an ASCII byte) to the locations in `str` at which it occurs.
-fn find_literal_digit_occurrences(line: &str) -> HashMap<u8, Vec<usize>> {
-    let numbers = [
-        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
-    ];
-
-    let result: HashMap<_, _> = numbers
-        .iter()
-        .enumerate()
-        .filter_map(|(i, &number)| {
-            let matches: Vec<_> = line.match_indices(number).collect();
-
-            if matches.is_empty() {
-                None
-            } else {
-                let digit_bytes = (i + 1).to_string().into_bytes();
-                assert_eq!(digit_bytes.len(), 1);
-                Some((
-                    digit_bytes[0],
-                    matches.iter().map(|&(loc, _)| loc).collect::<Vec<_>>(),
-                ))
-            }
-        })
-        .collect();
-    result
+fn part1(input: &str) -> String {
+    let rule = CalibrationRule {
+        alphabet: Alphabet::decimal(),
+        n: 1,
+        combine: Combine::Concatenate,
+    };
+    rule.total(input).to_string()
 }
 
 fn part2(input: &str) -> String {
-    let answer: u32 = input
-        .lines()
-        .map(|line| {
-            let digits: Vec<_> = replace_string_numbers(line)
-                .chars()
-                .filter_map(|x| {
-                    if x.is_ascii_digit() {
-                        Some(x.to_string().parse::<u32>().unwrap())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            let first_digit = digits.first().unwrap();
-            let last_digit = digits.last().unwrap();
-            first_digit * 10 + last_digit
-        })
-        .sum();
-
-    answer.to_string()
+    let rule = CalibrationRule {
+        alphabet: Alphabet::decimal().with(&Alphabet::english_words()),
+        n: 1,
+        combine: Combine::Concatenate,
+    };
+    rule.total(input).to_string()
 }
 
 fn main() {
@@ -115,7 +165,7 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use crate::{part1, part2};
+    use crate::{part1, part2, Alphabet, CalibrationRule, Combine};
 
     fn get_example_1() -> &'static str {
         include_str!("../example_1.txt")
@@ -134,4 +184,39 @@ mod tests {
     fn part_2() {
         assert_eq!(part2(get_example_2()), "281");
     }
+
+    #[test]
+    fn find_overlapping_values() {
+        let alphabet = Alphabet::decimal().with(&Alphabet::english_words());
+        assert_eq!(alphabet.find_values("eightwo3"), [8, 2, 3]);
+        assert_eq!(alphabet.find_values("xtwone3four"), [2, 1, 3, 4]);
+        assert_eq!(Alphabet::hex().find_values("1xfz0a"), [1, 15, 0, 10]);
+        assert_eq!(Alphabet::roman().find_values("aXbLI"), [10, 50, 1]);
+    }
+
+    #[test]
+    fn rule_variants() {
+        let rule = |alphabet: Alphabet, n, combine| CalibrationRule {
+            alphabet,
+            n,
+            combine,
+        };
+
+        let hex = rule(Alphabet::hex(), 1, Combine::Concatenate);
+        assert_eq!(hex.value("zz1ff"), Some(0x1f));
+
+        let wide = rule(Alphabet::decimal(), 2, Combine::Concatenate);
+        assert_eq!(wide.value("1a2b3c4"), Some(1234));
+        assert_eq!(wide.value("12"), Some(1212));
+        assert_eq!(wide.value("1"), None);
+
+        let sum = rule(Alphabet::decimal(), 2, Combine::Sum);
+        assert_eq!(sum.value("1a2b3c4"), Some(10));
+
+        let product = rule(Alphabet::decimal(), 1, Combine::Product);
+        assert_eq!(product.value("7pqrstsixteen"), Some(49));
+
+        let roman = rule(Alphabet::roman(), 1, Combine::Concatenate);
+        assert_eq!(roman.value("I..L"), Some(150));
+    }
 }
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{collections::BTreeMap, env, fmt, str::FromStr};
 
 fn get_input() -> &'static str {
     include_str!("../input.txt")
@@ -7,11 +7,37 @@ fn get_input() -> &'static str {
 // We need to derive from `PartialEq` to support `Eq`.
 // We need to derive from `Eq` to support checking in tests.
 // We need to derive from `Debug` to support
-#[derive(Debug, PartialEq, Eq, Clone)]
+//
+// NOTE: Colours are kept in a `BTreeMap` rather than a `HashMap` so that they are
+//  iterated in a consistent order.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
 struct CubeCount {
-    red: u32,
-    green: u32,
-    blue: u32,
+    counts: BTreeMap<String, u32>,
+}
+
+impl CubeCount {
+    // The number of cubes of the given colour; zero if the colour is not present.
+    fn get(&self, colour: &str) -> u32 {
+        self.counts.get(colour).copied().unwrap_or(0)
+    }
+
+    // Return true iff every colour in `self` is within the count for that colour in `limit`.
+    // Colours that are absent from `limit` are not allowed at all.
+    fn is_within(&self, limit: &CubeCount) -> bool {
+        self.counts
+            .iter()
+            .all(|(colour, &count)| count <= limit.get(colour))
+    }
+}
+
+impl<const N: usize> From<[(&str, u32); N]> for CubeCount {
+    fn from(pairs: [(&str, u32); N]) -> Self {
+        let mut cube_count = CubeCount::default();
+        for (colour, count) in pairs {
+            *cube_count.counts.entry(colour.to_string()).or_insert(0) += count;
+        }
+        cube_count
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -30,26 +56,18 @@ impl FromStr for CubeCount {
     type Err = ParseCubeCountError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut r: u32 = 0;
-        let mut g: u32 = 0;
-        let mut b: u32 = 0;
+        let mut counts: BTreeMap<String, u32> = BTreeMap::new();
 
         for tok in s.split(", ") {
             let (count, colour) = tok.split_once(' ').ok_or(ParseCubeCountError)?;
             let i_count: u32 = count.parse().map_err(|_| ParseCubeCountError)?;
-            match colour {
-                "red" => r += i_count,
-                "green" => g += i_count,
-                "blue" => b += i_count,
-                _ => return Err(ParseCubeCountError),
+            if colour.is_empty() || colour.contains(char::is_whitespace) {
+                return Err(ParseCubeCountError);
             }
+            *counts.entry(colour.to_string()).or_insert(0) += i_count;
         }
 
-        Ok(CubeCount {
-            red: r,
-            green: g,
-            blue: b,
-        })
+        Ok(CubeCount { counts })
     }
 }
 
@@ -82,85 +100,596 @@ impl FromStr for Game {
     }
 }
 
-fn part1(input: &str) -> String {
-    let answer: u32 = input
+fn parse_games(input: &str) -> Vec<Game> {
+    input
         .trim()
         .lines()
-        .map(|line| {
-            let game = Game::from_str(line).unwrap();
-            let is_possible = game
-                .cube_counts
-                .iter()
-                .all(|x| x.red <= 12 && x.green <= 13 && x.blue <= 14);
+        .map(|line| Game::from_str(line).unwrap())
+        .collect()
+}
 
-            if is_possible {
-                game.id
-            } else {
-                0
-            }
-        })
+// A single colour in a single draw of a game that exceeded what the bag could hold.
+#[derive(Debug, PartialEq, Eq)]
+struct Violation {
+    // Index of the offending draw within the game.
+    draw: usize,
+    colour: String,
+    count: u32,
+    limit: u32,
+}
+
+impl Violation {
+    // How many more cubes of this colour were drawn than the bag holds.
+    fn excess(&self) -> u32 {
+        self.count - self.limit
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "draw {}: {} {} > {} (by {})",
+            self.draw,
+            self.count,
+            self.colour,
+            self.limit,
+            self.excess()
+        )
+    }
+}
+
+impl Game {
+    fn is_possible(&self, bag: &CubeCount) -> bool {
+        self.cube_counts.iter().all(|x| x.is_within(bag))
+    }
+
+    // Every (draw, colour) in this game which could not have come from `bag`.
+    fn violations(&self, bag: &CubeCount) -> Vec<Violation> {
+        self.cube_counts
+            .iter()
+            .enumerate()
+            .flat_map(|(draw, cube_count)| {
+                cube_count
+                    .counts
+                    .iter()
+                    .filter_map(move |(colour, &count)| {
+                        let limit = bag.get(colour);
+                        (count > limit).then(|| Violation {
+                            draw,
+                            colour: colour.clone(),
+                            count,
+                            limit,
+                        })
+                    })
+            })
+            .collect()
+    }
+}
+
+// For every game that is impossible with `bag`, the reasons why.
+fn violation_report<'a>(games: &'a [Game], bag: &CubeCount) -> Vec<(&'a Game, Vec<Violation>)> {
+    games
+        .iter()
+        .map(|game| (game, game.violations(bag)))
+        .filter(|(_, violations)| !violations.is_empty())
+        .collect()
+}
+
+fn part1(input: &str, bag: &CubeCount) -> String {
+    let answer: u32 = parse_games(input)
+        .iter()
+        .filter(|game| game.is_possible(bag))
+        .map(|game| game.id)
         .sum();
 
     answer.to_string()
 }
 
+// The smallest bag, over all colours that appear, which could have produced all `cube_counts`.
 fn count_lower_bound(cube_counts: &[CubeCount]) -> CubeCount {
-    let mut red: u32 = 0;
-    let mut green: u32 = 0;
-    let mut blue: u32 = 0;
+    let mut counts: BTreeMap<String, u32> = BTreeMap::new();
     for cube_count in cube_counts {
-        red = red.max(cube_count.red);
-        green = green.max(cube_count.green);
-        blue = blue.max(cube_count.blue);
+        for (colour, &count) in &cube_count.counts {
+            let entry = counts.entry(colour.clone()).or_insert(0);
+            *entry = (*entry).max(count);
+        }
     }
-    CubeCount { red, green, blue }
+    CubeCount { counts }
 }
 
+// The colours whose counts make up the power of a game in part 2.
+const PUZZLE_COLOURS: [&str; 3] = ["red", "green", "blue"];
+
 fn part2(input: &str) -> String {
-    let answer: u32 = input
-        .trim()
-        .lines()
-        .map(|line| {
-            let game = Game::from_str(line).unwrap();
+    let answer: u32 = parse_games(input)
+        .iter()
+        .map(|game| {
+            // NOTE: A colour that never appears counts as zero, so the power is zero too.
             let cube_count = count_lower_bound(&game.cube_counts);
-            cube_count.red * cube_count.green * cube_count.blue
+            PUZZLE_COLOURS
+                .iter()
+                .map(|colour| cube_count.get(colour))
+                .product::<u32>()
         })
         .sum();
 
     answer.to_string()
 }
 
+// Estimating what is in the bag, rather than just bounding it.
+#[cfg_attr(not(test), expect(dead_code))]
+mod inference {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::ops::RangeInclusive;
+
+    use crate::{CubeCount, Game};
+
+    // How the cubes in each draw are taken out of the bag. In both cases the cubes are
+    // put back before the next draw.
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) enum Sampling {
+        // Each cube is put back before the next cube in the same draw is taken.
+        WithReplacement,
+        // All cubes of a draw are taken out at once.
+        WithoutReplacement,
+    }
+
+    // Prior weights for the total number of cubes in the bag. Given the total, every way
+    // of splitting it between the colours is considered equally likely.
+    pub(crate) struct TotalPrior {
+        weights: Vec<(u32, f64)>,
+    }
+
+    impl TotalPrior {
+        // The weights need not be normalised.
+        pub(crate) fn new(weights: Vec<(u32, f64)>) -> Self {
+            assert!(
+                weights.iter().all(|&(_, w)| w >= 0.0),
+                "Prior weights must be non-negative."
+            );
+            Self { weights }
+        }
+
+        pub(crate) fn uniform(totals: RangeInclusive<u32>) -> Self {
+            Self::new(totals.map(|total| (total, 1.0)).collect())
+        }
+    }
+
+    // A normalised posterior distribution over the contents of the bag, for the colours
+    // that were seen in a game.
+    pub(crate) struct Posterior {
+        colours: Vec<String>,
+        // Counts are in the same order as `colours`.
+        bags: Vec<(Vec<u32>, f64)>,
+    }
+
+    impl Posterior {
+        fn to_cube_count(&self, counts: &[u32]) -> CubeCount {
+            CubeCount {
+                counts: self
+                    .colours
+                    .iter()
+                    .cloned()
+                    .zip(counts.iter().copied())
+                    .collect(),
+            }
+        }
+
+        // The bag with the highest posterior probability.
+        pub(crate) fn most_likely(&self) -> CubeCount {
+            // NOTE: `bags` is never empty, since we refuse to construct a posterior with no
+            //  support.
+            let (counts, _) = self
+                .bags
+                .iter()
+                .max_by(|(_, p1), (_, p2)| p1.total_cmp(p2))
+                .unwrap();
+            self.to_cube_count(counts)
+        }
+
+        // Every bag with non-zero posterior probability, with that probability.
+        pub(crate) fn distribution(&self) -> Vec<(CubeCount, f64)> {
+            self.bags
+                .iter()
+                .map(|(counts, p)| (self.to_cube_count(counts), *p))
+                .collect()
+        }
+
+        // The posterior distribution of the number of cubes of a single colour.
+        pub(crate) fn marginal(&self, colour: &str) -> Option<BTreeMap<u32, f64>> {
+            let i = self.colours.iter().position(|x| x == colour)?;
+            let mut result: BTreeMap<u32, f64> = BTreeMap::new();
+            for (counts, p) in &self.bags {
+                *result.entry(counts[i]).or_insert(0.0) += p;
+            }
+            Some(result)
+        }
+
+        // An equal-tailed credible interval (inclusive) for the number of cubes of a
+        // single colour, e.g. `level = 0.9` leaves 5% of the probability on either side.
+        pub(crate) fn credible_interval(&self, colour: &str, level: f64) -> Option<(u32, u32)> {
+            assert!((0.0..=1.0).contains(&level));
+            let marginal = self.marginal(colour)?;
+            let tail = (1.0 - level) / 2.0;
+
+            // Smallest count at which the cumulative probability reaches `threshold`.
+            let quantile = |threshold: f64| {
+                let mut cumulative = 0.0;
+                for (&count, &p) in &marginal {
+                    cumulative += p;
+                    if cumulative >= threshold {
+                        return count;
+                    }
+                }
+                // Only reachable through rounding error.
+                *marginal.keys().next_back().unwrap()
+            };
+
+            Some((quantile(tail), quantile(1.0 - tail)))
+        }
+
+        // Credible intervals for every colour.
+        pub(crate) fn credible_intervals(&self, level: f64) -> BTreeMap<String, (u32, u32)> {
+            self.colours
+                .iter()
+                .map(|colour| {
+                    let interval = self.credible_interval(colour, level).unwrap();
+                    (colour.clone(), interval)
+                })
+                .collect()
+        }
+    }
+
+    fn ln_factorial(n: u32) -> f64 {
+        (2..=n).map(|i| f64::from(i).ln()).sum()
+    }
+
+    fn ln_choose(n: u32, k: u32) -> f64 {
+        ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k)
+    }
+
+    // Log-likelihood of seeing `draw` from a bag with contents `bag`, or `None` if it is
+    // impossible. Both are in the same colour order.
+    fn ln_likelihood(bag: &[u32], draw: &[u32], sampling: Sampling) -> Option<f64> {
+        let total: u32 = bag.iter().sum();
+        let n: u32 = draw.iter().sum();
+        match sampling {
+            Sampling::WithReplacement => {
+                // Multinomial.
+                let mut result = ln_factorial(n);
+                for (&c, &x) in bag.iter().zip(draw) {
+                    if x == 0 {
+                        continue;
+                    }
+                    if c == 0 {
+                        return None;
+                    }
+                    result += f64::from(x) * (f64::from(c) / f64::from(total)).ln();
+                    result -= ln_factorial(x);
+                }
+                Some(result)
+            }
+            Sampling::WithoutReplacement => {
+                // Multivariate hypergeometric.
+                if n > total || bag.iter().zip(draw).any(|(c, x)| x > c) {
+                    return None;
+                }
+                let numerator: f64 = bag.iter().zip(draw).map(|(&c, &x)| ln_choose(c, x)).sum();
+                Some(numerator - ln_choose(total, n))
+            }
+        }
+    }
+
+    // Call `f` with every way of splitting `total` into `k` non-negative counts.
+    fn for_each_composition(total: u32, k: usize, f: &mut impl FnMut(&[u32])) {
+        fn recurse(remaining: u32, k: usize, current: &mut Vec<u32>, f: &mut impl FnMut(&[u32])) {
+            if current.len() + 1 == k {
+                current.push(remaining);
+                f(current);
+                current.pop();
+                return;
+            }
+            for x in 0..=remaining {
+                current.push(x);
+                recurse(remaining - x, k, current, f);
+                current.pop();
+            }
+        }
+
+        if k == 0 {
+            if total == 0 {
+                f(&[]);
+            }
+            return;
+        }
+        recurse(total, k, &mut Vec::with_capacity(k), f);
+    }
+
+    // The posterior over the bag contents given all draws in `game`. Returns `None` if no
+    // bag allowed by `prior` could have produced the game.
+    pub(crate) fn posterior(
+        game: &Game,
+        prior: &TotalPrior,
+        sampling: Sampling,
+    ) -> Option<Posterior> {
+        // Only the colours that we have seen can be estimated.
+        let colours: Vec<String> = game
+            .cube_counts
+            .iter()
+            .flat_map(|x| x.counts.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if colours.is_empty() {
+            return None;
+        }
+        let k = u32::try_from(colours.len()).unwrap();
+        let draws: Vec<Vec<u32>> = game
+            .cube_counts
+            .iter()
+            .map(|x| colours.iter().map(|colour| x.get(colour)).collect())
+            .collect();
+
+        // Unnormalised log-posterior for every possible bag.
+        let mut ln_bags: Vec<(Vec<u32>, f64)> = Vec::new();
+        for &(total, weight) in &prior.weights {
+            if weight == 0.0 {
+                continue;
+            }
+            // Uniform over the compositions of this total.
+            let ln_prior = weight.ln() - ln_choose(total + k - 1, k - 1);
+
+            for_each_composition(total, colours.len(), &mut |bag| {
+                let ln_likelihood: Option<f64> = draws
+                    .iter()
+                    .map(|draw| ln_likelihood(bag, draw, sampling))
+                    .sum();
+                if let Some(ln_likelihood) = ln_likelihood {
+                    ln_bags.push((bag.to_vec(), ln_prior + ln_likelihood));
+                }
+            });
+        }
+
+        // Normalise, subtracting the maximum first for numerical stability.
+        let max = ln_bags.iter().map(|&(_, x)| x).max_by(f64::total_cmp)?;
+        let bags: Vec<(Vec<u32>, f64)> = ln_bags
+            .into_iter()
+            .map(|(bag, x)| (bag, (x - max).exp()))
+            .collect();
+        let norm: f64 = bags.iter().map(|&(_, p)| p).sum();
+        let bags = bags.into_iter().map(|(bag, p)| (bag, p / norm)).collect();
+
+        Some(Posterior { colours, bags })
+    }
+
+    // The posterior for every game, keyed by game id.
+    pub(crate) fn posteriors(
+        games: &[Game],
+        prior: &TotalPrior,
+        sampling: Sampling,
+    ) -> BTreeMap<u32, Option<Posterior>> {
+        games
+            .iter()
+            .map(|game| (game.id, posterior(game, prior, sampling)))
+            .collect()
+    }
+}
+
+// Choosing a single bag for many games at once.
+#[cfg_attr(not(test), expect(dead_code))]
+mod optimise {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::{count_lower_bound, CubeCount, Game};
+
+    // How to measure the size of a bag.
+    pub(crate) enum BagCost {
+        // The total number of cubes.
+        Total,
+        // The number of cubes of each colour multiplied by its weight. Colours without a
+        // weight count as 1.
+        Weighted(BTreeMap<String, u64>),
+    }
+
+    impl BagCost {
+        fn weight(&self, colour: &str) -> u64 {
+            match self {
+                BagCost::Total => 1,
+                BagCost::Weighted(weights) => weights.get(colour).copied().unwrap_or(1),
+            }
+        }
+    }
+
+    // A point on the trade-off between bag size and the number of possible games.
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) struct FrontierPoint {
+        pub(crate) bag: CubeCount,
+        pub(crate) cost: u64,
+        pub(crate) num_possible: usize,
+    }
+
+    // Call `f` with every combination that takes one value from each of `candidates`.
+    fn for_each_combination(candidates: &[Vec<u32>], f: &mut impl FnMut(&[u32])) {
+        fn recurse(candidates: &[Vec<u32>], current: &mut Vec<u32>, f: &mut impl FnMut(&[u32])) {
+            let Some((first, rest)) = candidates.split_first() else {
+                f(current);
+                return;
+            };
+            for &x in first {
+                current.push(x);
+                recurse(rest, current, f);
+                current.pop();
+            }
+        }
+        recurse(candidates, &mut Vec::with_capacity(candidates.len()), f);
+    }
+
+    // The cheapest bag that makes at least `k` games possible, for every `k` from 0 up to
+    // the number of games.
+    //
+    // A game is possible iff the bag covers its lower bound, so in an optimal bag the count
+    // of each colour is the requirement of one of the games (or zero). We try every such
+    // count for all but the last colour; for the last colour, the cheapest count for each
+    // `k` can then be read off from the sorted requirements of the remaining games.
+    // PERF: This is exponential in the number of colours, but that is small in practice.
+    fn cheapest_bags(games: &[Game], cost: &BagCost) -> Vec<(u64, CubeCount)> {
+        let bounds: Vec<CubeCount> = games
+            .iter()
+            .map(|game| count_lower_bound(&game.cube_counts))
+            .collect();
+        let colours: Vec<String> = bounds
+            .iter()
+            .flat_map(|x| x.counts.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let weights: Vec<u64> = colours.iter().map(|x| cost.weight(x)).collect();
+        let requirements: Vec<Vec<u32>> = bounds
+            .iter()
+            .map(|bound| colours.iter().map(|colour| bound.get(colour)).collect())
+            .collect();
+
+        let mut best: Vec<Option<(u64, Vec<u32>)>> = vec![None; games.len() + 1];
+        best[0] = Some((0, vec![0; colours.len()]));
+
+        let Some(last) = colours.len().checked_sub(1) else {
+            // No colours at all, so every game is possible with an empty bag.
+            return vec![(0, CubeCount::default()); games.len() + 1];
+        };
+
+        let candidates: Vec<Vec<u32>> = (0..last)
+            .map(|i| {
+                let mut values: Vec<u32> = requirements.iter().map(|r| r[i]).collect();
+                values.push(0);
+                values.sort_unstable();
+                values.dedup();
+                values
+            })
+            .collect();
+
+        for_each_combination(&candidates, &mut |fixed| {
+            let fixed_cost: u64 = fixed
+                .iter()
+                .zip(&weights)
+                .map(|(&x, &w)| u64::from(x) * w)
+                .sum();
+
+            let mut last_values: Vec<u32> = requirements
+                .iter()
+                .filter(|r| r[..last].iter().zip(fixed).all(|(x, y)| x <= y))
+                .map(|r| r[last])
+                .collect();
+            last_values.sort_unstable();
+
+            for (i, &value) in last_values.iter().enumerate() {
+                let this_cost = fixed_cost + u64::from(value) * weights[last];
+                let entry = &mut best[i + 1];
+                if entry.as_ref().map_or(true, |(c, _)| this_cost < *c) {
+                    let mut bag = fixed.to_vec();
+                    bag.push(value);
+                    *entry = Some((this_cost, bag));
+                }
+            }
+        });
+
+        // Every `k` is achievable, since the bag covering all games covers any subset.
+        best.into_iter()
+            .map(|x| {
+                let (c, counts) = x.unwrap();
+                let bag = CubeCount {
+                    counts: colours.iter().cloned().zip(counts).collect(),
+                };
+                (c, bag)
+            })
+            .collect()
+    }
+
+    // The cheapest bag with which at least `min_possible` of `games` are possible, or `None`
+    // if there are fewer games than that.
+    pub(crate) fn smallest_bag(
+        games: &[Game],
+        min_possible: usize,
+        cost: &BagCost,
+    ) -> Option<CubeCount> {
+        cheapest_bags(games, cost)
+            .into_iter()
+            .nth(min_possible)
+            .map(|(_, bag)| bag)
+    }
+
+    // The bags for which no other bag is both no more expensive and makes more games
+    // possible, in increasing order of cost.
+    pub(crate) fn pareto_frontier(games: &[Game], cost: &BagCost) -> Vec<FrontierPoint> {
+        let bags = cheapest_bags(games, cost);
+        let mut frontier: Vec<FrontierPoint> = Vec::new();
+        for (num_possible, (cost, bag)) in bags.into_iter().enumerate().skip(1) {
+            // A point costing the same as the previous one dominates it.
+            if frontier.last().is_some_and(|x| x.cost == cost) {
+                frontier.pop();
+            }
+            frontier.push(FrontierPoint {
+                bag,
+                cost,
+                num_possible,
+            });
+        }
+        frontier
+    }
+}
+
 fn main() {
     let input = get_input();
-    println!("Part1: {}", part1(input));
+
+    // The bag can be overridden on the command line, e.g. "12 red, 13 green, 14 blue".
+    // If it is, we also report why each impossible game is impossible.
+    let bag_arg = env::args().nth(1);
+    let bag: CubeCount = bag_arg
+        .as_deref()
+        .unwrap_or("12 red, 13 green, 14 blue")
+        .parse()
+        .expect("Could not parse bag");
+
+    println!("Part1: {}", part1(input, &bag));
     println!("Part2: {}", part2(input));
+
+    if bag_arg.is_some() {
+        for (game, violations) in violation_report(&parse_games(input), &bag) {
+            println!("Game {}:", game.id);
+            for violation in violations {
+                println!("  {violation}");
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use crate::{part1, part2, CubeCount, Game, ParseCubeCountError, ParseGameError};
+    use crate::inference::{posterior, posteriors, Sampling, TotalPrior};
+    use crate::optimise::{pareto_frontier, smallest_bag, BagCost};
+    use crate::{
+        count_lower_bound, parse_games, part1, part2, violation_report, CubeCount, Game,
+        ParseCubeCountError, ParseGameError, Violation,
+    };
 
     #[test]
     fn cube_count_from_str() {
         assert_eq!(
             CubeCount::from_str("3 blue, 4 red"),
-            Ok(CubeCount {
-                red: 4,
-                green: 0,
-                blue: 3
-            })
+            Ok(CubeCount::from([("red", 4), ("blue", 3)]))
         );
         assert_eq!(
             CubeCount::from_str("3 green, 4 blue, 1 red"),
-            Ok(CubeCount {
-                red: 1,
-                green: 3,
-                blue: 4
-            })
+            Ok(CubeCount::from([("red", 1), ("green", 3), ("blue", 4)]))
+        );
+        assert_eq!(
+            CubeCount::from_str("2 purple, 1 red, 3 purple"),
+            Ok(CubeCount::from([("red", 1), ("purple", 5)]))
         );
         assert_eq!(CubeCount::from_str("bad"), Err(ParseCubeCountError));
+        assert_eq!(CubeCount::from_str("3 "), Err(ParseCubeCountError));
     }
 
     #[test]
@@ -170,21 +699,9 @@ mod tests {
             Ok(Game {
                 id: 42,
                 cube_counts: [
-                    CubeCount {
-                        red: 4,
-                        green: 0,
-                        blue: 3
-                    },
-                    CubeCount {
-                        red: 1,
-                        green: 2,
-                        blue: 6
-                    },
-                    CubeCount {
-                        red: 0,
-                        green: 2,
-                        blue: 0
-                    }
+                    CubeCount::from([("red", 4), ("blue", 3)]),
+                    CubeCount::from([("red", 1), ("green", 2), ("blue", 6)]),
+                    CubeCount::from([("green", 2)]),
                 ]
                 .to_vec()
             }),
@@ -200,13 +717,146 @@ Game 4: 1 green, 3 red, 6 blue; 3 green, 6 red; 3 green, 15 blue, 14 red
 Game 5: 6 red, 1 blue, 3 green; 2 blue, 1 red, 2 green
 ";
 
+    #[test]
+    fn extra_colours() {
+        let game =
+            Game::from_str("Game 7: 3 blue, 4 red; 2 purple, 1 red; 5 purple, 1 blue").unwrap();
+        let bound = count_lower_bound(&game.cube_counts);
+        assert_eq!(
+            bound,
+            CubeCount::from([("red", 4), ("blue", 3), ("purple", 5)])
+        );
+
+        let without_purple = CubeCount::from([("red", 12), ("green", 13), ("blue", 14)]);
+        assert!(!bound.is_within(&without_purple));
+        let with_purple = CubeCount::from([("red", 12), ("blue", 14), ("purple", 5)]);
+        assert!(bound.is_within(&with_purple));
+    }
+
+    fn puzzle_bag() -> CubeCount {
+        CubeCount::from([("red", 12), ("green", 13), ("blue", 14)])
+    }
+
+    #[test]
+    fn posterior_two_colours() {
+        // With four cubes, seeing one of each colour twice favours an even split.
+        let game = Game::from_str("Game 1: 1 red, 1 blue; 1 blue, 1 red").unwrap();
+        let prior = TotalPrior::uniform(4..=4);
+
+        for sampling in [Sampling::WithReplacement, Sampling::WithoutReplacement] {
+            let posterior = posterior(&game, &prior, sampling).unwrap();
+            assert_eq!(
+                posterior.most_likely(),
+                CubeCount::from([("red", 2), ("blue", 2)])
+            );
+
+            let marginal = posterior.marginal("red").unwrap();
+            let expected = [(1, 9.0 / 34.0), (2, 16.0 / 34.0), (3, 9.0 / 34.0)];
+            assert_eq!(marginal.len(), expected.len());
+            for (count, p) in expected {
+                assert!((marginal[&count] - p).abs() < 1e-12);
+            }
+
+            assert_eq!(posterior.credible_interval("red", 0.5), Some((1, 3)));
+            assert_eq!(posterior.credible_interval("red", 0.4), Some((2, 2)));
+            assert_eq!(posterior.credible_interval("green", 0.4), None);
+        }
+    }
+
+    #[test]
+    fn posterior_respects_lower_bound() {
+        let games = parse_games(EXAMPLE);
+        let prior = TotalPrior::uniform(0..=40);
+        let posteriors = posteriors(&games, &prior, Sampling::WithoutReplacement);
+
+        for game in &games {
+            let bound = count_lower_bound(&game.cube_counts);
+            let posterior = posteriors[&game.id].as_ref().unwrap();
+
+            let total: f64 = posterior.distribution().iter().map(|(_, p)| p).sum();
+            assert!((total - 1.0).abs() < 1e-9);
+
+            for (colour, (lo, hi)) in posterior.credible_intervals(0.9) {
+                assert!(lo >= bound.get(&colour));
+                assert!(lo <= hi);
+            }
+        }
+
+        // Game 3 has 20 red in one draw, so can't come from a bag of 20 cubes.
+        let small = TotalPrior::uniform(0..=20);
+        assert!(posterior(&games[2], &small, Sampling::WithoutReplacement).is_none());
+    }
+
+    #[test]
+    fn shared_bag() {
+        let games = parse_games(EXAMPLE);
+
+        let frontier = pareto_frontier(&games, &BagCost::Total);
+        let points: Vec<_> = frontier.iter().map(|x| (x.num_possible, x.cost)).collect();
+        assert_eq!(points, [(1, 8), (2, 13), (3, 15), (4, 32), (5, 48)]);
+        assert_eq!(
+            frontier[2].bag,
+            CubeCount::from([("red", 6), ("green", 3), ("blue", 6)])
+        );
+        for point in &frontier {
+            let num_possible = games.iter().filter(|x| x.is_possible(&point.bag)).count();
+            assert_eq!(num_possible, point.num_possible);
+        }
+
+        // Expensive red cubes make a different pair of games the cheapest.
+        let weighted = BagCost::Weighted([("red".to_string(), 10)].into());
+        assert_eq!(
+            smallest_bag(&games, 2, &weighted),
+            Some(CubeCount::from([("red", 4), ("green", 3), ("blue", 6)]))
+        );
+        assert_eq!(smallest_bag(&games, 6, &weighted), None);
+    }
+
     #[test]
     fn part_1() {
-        assert_eq!(part1(EXAMPLE), "8");
+        assert_eq!(part1(EXAMPLE, &puzzle_bag()), "8");
+        assert_eq!(
+            part1(
+                EXAMPLE,
+                &CubeCount::from([("red", 20), ("green", 13), ("blue", 15)])
+            ),
+            "15"
+        );
+    }
+
+    #[test]
+    fn violations() {
+        let games = parse_games(EXAMPLE);
+        let report = violation_report(&games, &puzzle_bag());
+        let ids: Vec<u32> = report.iter().map(|(game, _)| game.id).collect();
+        assert_eq!(ids, [3, 4]);
+
+        assert_eq!(
+            report[0].1,
+            [Violation {
+                draw: 0,
+                colour: "red".to_string(),
+                count: 20,
+                limit: 12
+            }]
+        );
+        // Game 4 breaks two limits in its last draw.
+        let summary: Vec<_> = report[1]
+            .1
+            .iter()
+            .map(|x| (x.draw, x.colour.as_str(), x.excess()))
+            .collect();
+        assert_eq!(summary, [(2, "blue", 1), (2, "red", 2)]);
     }
 
     #[test]
     fn part_2() {
         assert_eq!(part2(EXAMPLE), "2286");
     }
+
+    #[test]
+    fn part_2_missing_colour() {
+        assert_eq!(part2("Game 1: 3 red, 2 blue"), "0");
+        assert_eq!(part2("Game 1: 3 red, 2 blue, 1 purple; 1 green"), "6");
+    }
 }
@@ -1,261 +1,1069 @@
-use std::{collections::HashSet, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 fn get_input() -> &'static str {
     include_str!("../input.txt")
 }
 
-// Represent a (row, col) location in the schematic.
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+// Represent a (layer, row, col) location in the schematic. Schematics that aren't
+// layered only use layer 0.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 struct Location {
+    layer: usize,
     row: usize,
     col: usize,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 struct Extent {
+    layer: usize,
     row: usize,
     col_begin: usize, // Inclusive
     col_end: usize,   // Exclusive
 }
 
+impl Extent {
+    // The extent covering just the given location.
+    fn cell(location: Location) -> Extent {
+        Extent {
+            layer: location.layer,
+            row: location.row,
+            col_begin: location.col,
+            col_end: location.col + 1,
+        }
+    }
+
+    fn start(self) -> Location {
+        Location {
+            layer: self.layer,
+            row: self.row,
+            col: self.col_begin,
+        }
+    }
+
+    fn contains(self, location: Location) -> bool {
+        location.layer == self.layer
+            && location.row == self.row
+            && (self.col_begin..self.col_end).contains(&location.col)
+    }
+
+    fn locations(self) -> impl Iterator<Item = Location> {
+        (self.col_begin..self.col_end).map(move |col| Location {
+            layer: self.layer,
+            row: self.row,
+            col,
+        })
+    }
+
+    // The locations touching this extent, including diagonally and in the layers above and
+    // below, but not the extent itself.
+    fn neighbours(self) -> impl Iterator<Item = Location> {
+        let layers = self.layer.saturating_sub(1)..=(self.layer + 1);
+        layers
+            .flat_map(move |layer| {
+                let rows = self.row.saturating_sub(1)..=(self.row + 1);
+                rows.flat_map(move |row| {
+                    let cols = self.col_begin.saturating_sub(1)..=self.col_end;
+                    cols.map(move |col| Location { layer, row, col })
+                })
+            })
+            .filter(move |&x| !self.contains(x))
+    }
+}
+
+// NOTE: Symbols are kept as strings, since a single grapheme cluster can be made of several
+//  `char`s. They can also take up more than one column.
 #[derive(Debug)]
 struct Schematic {
-    numbers: Vec<(Extent, u32)>,
-    symbols: Vec<(Location, char)>,
+    numbers: Vec<(Extent, i64)>,
+    symbols: Vec<(Extent, String)>,
+
+    // Acceleration structures, mapping every occupied location to the index of the
+    // number or symbol that occupies it.
+    number_at: HashMap<Location, usize>,
+    symbol_at: HashMap<Location, usize>,
+}
+
+impl Schematic {
+    fn new(numbers: Vec<(Extent, i64)>, symbols: Vec<(Extent, String)>) -> Schematic {
+        let number_at = numbers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (extent, _))| extent.locations().map(move |x| (x, i)))
+            .collect();
+        let symbol_at = symbols
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (extent, _))| extent.locations().map(move |x| (x, i)))
+            .collect();
+        Schematic {
+            numbers,
+            symbols,
+            number_at,
+            symbol_at,
+        }
+    }
+
+    // Indices of the numbers adjacent to the symbol with index `i_symbol`, in ascending order.
+    fn adjacent_numbers(&self, i_symbol: usize) -> Vec<usize> {
+        let (extent, _) = &self.symbols[i_symbol];
+        let mut result: Vec<usize> = extent
+            .neighbours()
+            .filter_map(|x| self.number_at.get(&x).copied())
+            .collect();
+        // A number can touch the symbol in more than one place.
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    // Indices of the symbols adjacent to the number with index `i_number`, in ascending order.
+    fn adjacent_symbols(&self, i_number: usize) -> Vec<usize> {
+        let (extent, _) = &self.numbers[i_number];
+        let mut result: Vec<usize> = extent
+            .neighbours()
+            .filter_map(|x| self.symbol_at.get(&x).copied())
+            .collect();
+        // Likewise, a wide symbol can touch the number in more than one place.
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
 }
 
 #[derive(Debug)]
 struct ParseSchematicError;
 
-// Flush the current number & symbols.
+// How columns are counted when parsing a schematic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ColumnMode {
+    // Every `char` is one column.
+    Chars,
+    // Every grapheme cluster is one column, so e.g. combining accents don't take up a column
+    // of their own.
+    #[cfg_attr(not(test), expect(dead_code))]
+    Graphemes,
+    // Every grapheme cluster takes up as many columns as it would in a terminal, so e.g. wide
+    // symbols take up two columns.
+    #[cfg_attr(not(test), expect(dead_code))]
+    DisplayWidth,
+}
+
+#[derive(Clone, Debug)]
+struct ParseOptions {
+    // Anything that is neither a digit nor a separator is a symbol.
+    separators: HashSet<char>,
+    // If set, a '-' directly before a digit is the sign of a number rather than a symbol.
+    negative_numbers: bool,
+    columns: ColumnMode,
+    // If set, the schematic is made up of layers stacked on top of each other, which are
+    // separated by blank lines.
+    layered: bool,
+}
+
+impl Default for ParseOptions {
+    // The rules from the puzzle.
+    fn default() -> Self {
+        ParseOptions {
+            separators: HashSet::from(['.']),
+            negative_numbers: false,
+            columns: ColumnMode::Chars,
+            layered: false,
+        }
+    }
+}
+
+// Split a line into tokens, each with the columns that it takes up.
+// NOTE: Tokens with no width (e.g. a zero-width space) are dropped, since they can't be
+//  adjacent to anything.
+fn tokenise(line: &str, mode: ColumnMode) -> Vec<(Range<usize>, &str)> {
+    let tokens: Vec<&str> = match mode {
+        ColumnMode::Chars => line
+            .char_indices()
+            .map(|(i, c)| &line[i..(i + c.len_utf8())])
+            .collect(),
+        ColumnMode::Graphemes | ColumnMode::DisplayWidth => line.graphemes(true).collect(),
+    };
+
+    let mut col = 0;
+    let mut result = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let width = match mode {
+            ColumnMode::Chars | ColumnMode::Graphemes => 1,
+            ColumnMode::DisplayWidth => token.width(),
+        };
+        if width > 0 {
+            result.push((col..(col + width), token));
+            col += width;
+        }
+    }
+    result
+}
+
+// Flush the current number, if any.
 fn flush_current_number(
+    layer: usize,
     row: usize,
-    current_number_col: usize,
-    current_number: &mut Vec<char>,
-    numbers: &mut Vec<(Extent, u32)>,
+    current_number: &mut Option<(Range<usize>, String)>,
+    numbers: &mut Vec<(Extent, i64)>,
 ) -> Result<(), ParseSchematicError> {
-    if current_number.is_empty() {
+    let Some((cols, text)) = current_number.take() else {
         return Ok(());
-    }
+    };
 
     // If there is a problem then this will return.
-    let number = current_number
-        .iter()
-        .collect::<String>()
-        .parse::<u32>()
-        .map_err(|_| ParseSchematicError)?;
+    let number = text.parse::<i64>().map_err(|_| ParseSchematicError)?;
 
     numbers.push((
         Extent {
+            layer,
             row,
-            col_begin: current_number_col,
-            col_end: current_number_col + current_number.len(),
+            col_begin: cols.start,
+            col_end: cols.end,
         },
         number,
     ));
 
-    // Empty the current number.
-    current_number.clear();
-
     Ok(())
 }
 
-impl FromStr for Schematic {
-    type Err = ParseSchematicError;
-    //
+// Split the lines of `s` into groups, which are separated by one or more blank lines.
+fn split_layers(s: &str) -> Vec<Vec<&str>> {
+    let mut layers: Vec<Vec<&str>> = vec![Vec::new()];
+    for line in s.lines() {
+        if !line.trim().is_empty() {
+            layers.last_mut().unwrap().push(line);
+        } else if !layers.last().unwrap().is_empty() {
+            layers.push(Vec::new());
+        }
+    }
+    layers
+}
 
-    fn from_str(s: &str) -> Result<Schematic, ParseSchematicError> {
+fn is_digit(token: &str) -> bool {
+    token.len() == 1 && token.as_bytes()[0].is_ascii_digit()
+}
+
+impl Schematic {
+    fn parse_with(s: &str, options: &ParseOptions) -> Result<Schematic, ParseSchematicError> {
         // First we extract all numbers, and their start and end locations, and all symbols and their
         // locations.
-        // NOTE: We don't care about what the symbols are, just their locations.
-        let mut numbers: Vec<(Extent, u32)> = Vec::new();
-        let mut symbols: Vec<(Location, char)> = Vec::new();
+        let mut numbers: Vec<(Extent, i64)> = Vec::new();
+        let mut symbols: Vec<(Extent, String)> = Vec::new();
 
-        for (i_line, line) in s.trim().lines().enumerate() {
+        let layers: Vec<Vec<&str>> = if options.layered {
+            split_layers(s.trim())
+        } else {
+            vec![s.trim().lines().collect()]
+        };
+
+        for (i_layer, i_line, line) in layers.iter().enumerate().flat_map(|(i_layer, lines)| {
+            lines
+                .iter()
+                .enumerate()
+                .map(move |(i_line, line)| (i_layer, i_line, line))
+        }) {
             // We are looking for contiguous runs of digits, which we will parse
             // as an integer.
-            // '.' is a separator.
-            // Any other characters are "symbols", which we need to keep track of separately.
-            let mut current_number_col: usize = 0;
-            let mut current_number: Vec<char> = Vec::new();
-
-            for (col, x) in line.chars().enumerate() {
-                if x == '.' {
-                    // A dot should flush the current number, but otherwise
-                    // be ignored.
-                    flush_current_number(
-                        i_line,
-                        current_number_col,
-                        &mut current_number,
-                        &mut numbers,
-                    )?;
-                } else if x.is_ascii_digit() {
-                    let start_of_number = current_number.is_empty();
-                    if start_of_number {
-                        // Set where the number starts iff this is a new integer.
-                        current_number_col = col;
-                    }
-                    // Always append the latest seen digit.
-                    current_number.push(x);
-                } else {
-                    // A symbol should cause the current number to be flushed.
-                    flush_current_number(
-                        i_line,
-                        current_number_col,
-                        &mut current_number,
-                        &mut numbers,
-                    )?;
-                    symbols.push((Location { row: i_line, col }, x));
+            // Separators (e.g. '.') are otherwise ignored.
+            // Any other tokens are "symbols", which we need to keep track of separately.
+            let tokens = tokenise(line, options.columns);
+            let mut current_number: Option<(Range<usize>, String)> = None;
+
+            for (i, (cols, token)) in tokens.iter().enumerate() {
+                if is_digit(token) {
+                    // Start a new number if required, and always append the latest seen digit.
+                    let (current_cols, text) =
+                        current_number.get_or_insert_with(|| (cols.clone(), String::new()));
+                    current_cols.end = cols.end;
+                    text.push_str(token);
+                    continue;
+                }
+
+                // Anything else should cause the current number to be flushed.
+                flush_current_number(i_layer, i_line, &mut current_number, &mut numbers)?;
+
+                let is_sign = options.negative_numbers
+                    && *token == "-"
+                    && tokens.get(i + 1).is_some_and(|(_, next)| is_digit(next));
+                let mut chars = token.chars();
+                let is_separator = chars
+                    .next()
+                    .is_some_and(|c| chars.next().is_none() && options.separators.contains(&c));
+
+                if is_sign {
+                    current_number = Some((cols.clone(), (*token).to_string()));
+                } else if !is_separator {
+                    let extent = Extent {
+                        layer: i_layer,
+                        row: i_line,
+                        col_begin: cols.start,
+                        col_end: cols.end,
+                    };
+                    symbols.push((extent, (*token).to_string()));
                 }
             }
 
             // Any digits left on the stack should be flushed.
-            flush_current_number(
-                i_line,
-                current_number_col,
-                &mut current_number,
-                &mut numbers,
-            )?;
+            flush_current_number(i_layer, i_line, &mut current_number, &mut numbers)?;
         }
 
-        Ok(Schematic { numbers, symbols })
+        Ok(Schematic::new(numbers, symbols))
     }
 }
 
-// This was used for debugging, so don't warn about the fact that it is unused.
-#[expect(dead_code)]
-fn render_locations(locations: &HashSet<Location>) -> String {
-    let n_rows = locations.iter().map(|x| x.row).max().unwrap() + 1;
-    let n_cols = locations.iter().map(|x| x.col).max().unwrap() + 1;
-
-    let mut output_lines: Vec<Vec<char>> = vec![vec!['.'; n_cols]; n_rows];
+impl FromStr for Schematic {
+    type Err = ParseSchematicError;
 
-    for location in locations {
-        output_lines[location.row][location.col] = '*';
+    fn from_str(s: &str) -> Result<Schematic, ParseSchematicError> {
+        Schematic::parse_with(s, &ParseOptions::default())
     }
-
-    // NOTE: The `collect` is required because `join` requires a collection.
-    output_lines
-        .iter()
-        .map(|x| x.iter().collect::<String>())
-        .collect::<Vec<String>>()
-        .join("\n")
 }
 
-// One before x... but x if x == 0
-fn one_before<T>(x: T) -> T
-where
-    T: std::ops::Sub<Output = T> + From<u8> + PartialEq,
-{
-    if x == 0.into() {
-        x
-    } else {
-        x - 1.into()
+impl Schematic {
+    // Part numbers are those which are adjacent to any symbol.
+    fn part_number_sum(&self) -> i64 {
+        (0..self.numbers.len())
+            .filter(|&i| !self.adjacent_symbols(i).is_empty())
+            .map(|i| self.numbers[i].1)
+            .sum()
     }
 }
 
 fn part1(input: &str) -> String {
     let schematic: Schematic = input.parse().unwrap();
+    schematic.part_number_sum().to_string()
+}
 
-    // By iterating over all symbol extents, we build up a set of locations
-    // that we should allow.
-    let mut allowed_locations: HashSet<Location> = HashSet::new();
-    for (location, _) in schematic.symbols {
-        // We validate everything around this location "diagonally".
-        let row_start = one_before(location.row);
-        let col_start = one_before(location.col);
-
-        for row in row_start..(location.row + 2) {
-            for col in col_start..(location.col + 2) {
-                allowed_locations.insert(Location { row, col });
-            }
+// How many numbers need to be adjacent to a symbol for it to be a gear.
+#[derive(Clone, Debug)]
+enum NeighbourCount {
+    Exactly(usize),
+    #[cfg_attr(not(test), expect(dead_code))]
+    AtLeast(usize),
+    #[cfg_attr(not(test), expect(dead_code))]
+    Between(RangeInclusive<usize>),
+}
+
+impl NeighbourCount {
+    fn matches(&self, n: usize) -> bool {
+        match self {
+            NeighbourCount::Exactly(x) => n == *x,
+            NeighbourCount::AtLeast(x) => n >= *x,
+            NeighbourCount::Between(range) => range.contains(&n),
         }
     }
+}
 
-    // Now we filter out the numbers that are allowed and sum them.
-    let answer: u32 = schematic
-        .numbers
-        .iter()
-        .filter(|(extent, _)| {
-            (extent.col_begin..extent.col_end).into_iter().any(|col| {
-                let location = Location {
-                    row: extent.row,
-                    col,
-                };
-                allowed_locations.contains(&location)
+// How the numbers adjacent to a gear are combined into its value.
+#[derive(Clone, Copy, Debug)]
+enum GearValue {
+    Product,
+    #[cfg_attr(not(test), expect(dead_code))]
+    Sum,
+    #[cfg_attr(not(test), expect(dead_code))]
+    Max,
+}
+
+impl GearValue {
+    fn apply(self, values: impl Iterator<Item = i64>) -> i64 {
+        match self {
+            GearValue::Product => values.product(),
+            GearValue::Sum => values.sum(),
+            GearValue::Max => values.max().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct GearRule {
+    symbols: HashSet<char>,
+    neighbours: NeighbourCount,
+    value: GearValue,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Gear {
+    location: Location,
+    symbol: char,
+    // Indices into `Schematic::numbers`, in ascending order.
+    numbers: Vec<usize>,
+    value: i64,
+}
+
+impl GearRule {
+    // A gear is a '*' with exactly two adjacent numbers, and its value is their product.
+    fn puzzle() -> GearRule {
+        GearRule {
+            symbols: HashSet::from(['*']),
+            neighbours: NeighbourCount::Exactly(2),
+            value: GearValue::Product,
+        }
+    }
+
+    // All gears in `schematic`, in the order in which the symbols appear.
+    // NOTE: Only symbols made up of a single `char` can be gears.
+    fn find_gears(&self, schematic: &Schematic) -> Vec<Gear> {
+        schematic
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (extent, text))| {
+                let mut chars = text.chars();
+                let symbol = chars.next()?;
+                if chars.next().is_some() || !self.symbols.contains(&symbol) {
+                    return None;
+                }
+                let numbers = schematic.adjacent_numbers(i);
+                if !self.neighbours.matches(numbers.len()) {
+                    return None;
+                }
+                let value = self
+                    .value
+                    .apply(numbers.iter().map(|&i| schematic.numbers[i].1));
+                Some(Gear {
+                    location: extent.start(),
+                    symbol,
+                    numbers,
+                    value,
+                })
             })
-        })
-        .map(|(_, number)| number)
+            .collect()
+    }
+}
+
+fn part2(input: &str) -> String {
+    // Parse the schematic as for part 1.
+    let schematic: Schematic = input.parse().unwrap();
+
+    let answer: i64 = GearRule::puzzle()
+        .find_gears(&schematic)
+        .iter()
+        .map(|gear| gear.value)
         .sum();
 
     answer.to_string()
 }
 
-// Return true iff `location` is adjacent to `Extent`.
-fn is_adjacent(extent: &Extent, location: &Location) -> bool {
-    if one_before(location.row) > extent.row {
-        return false;
+// What is drawn at each location when rendering a schematic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CellKind {
+    Empty,
+    PartNumber,
+    NonPartNumber,
+    Symbol,
+    Gear,
+}
+
+impl CellKind {
+    // Escape sequence selecting the terminal colour, or `None` to use the default.
+    fn ansi_style(self) -> Option<&'static str> {
+        match self {
+            CellKind::Empty => None,
+            CellKind::PartNumber => Some("\x1b[32m"),
+            CellKind::NonPartNumber => Some("\x1b[31m"),
+            CellKind::Symbol => Some("\x1b[33m"),
+            CellKind::Gear => Some("\x1b[1;35m"),
+        }
+    }
+
+    fn html_class(self) -> Option<&'static str> {
+        match self {
+            CellKind::Empty => None,
+            CellKind::PartNumber => Some("part"),
+            CellKind::NonPartNumber => Some("non-part"),
+            CellKind::Symbol => Some("symbol"),
+            CellKind::Gear => Some("gear"),
+        }
+    }
+}
+
+const HTML_STYLE: &str = "<style>
+.schematic .part { color: green; }
+.schematic .non-part { color: red; }
+.schematic .symbol { color: goldenrod; }
+.schematic .gear { color: purple; font-weight: bold; }
+</style>";
+
+// The text and kind of every location in one layer of a schematic, indexed by row and column.
+type Grid = Vec<Vec<(String, CellKind)>>;
+
+impl Schematic {
+    // Classify every location in the smallest grid that contains the whole schematic, with
+    // one grid per layer. The text of a symbol wider than one column is all in its first
+    // location.
+    fn classify(&self, rule: &GearRule) -> Vec<Grid> {
+        let extents = || {
+            self.numbers
+                .iter()
+                .map(|(extent, _)| extent)
+                .chain(self.symbols.iter().map(|(extent, _)| extent))
+        };
+        let n_layers = extents().map(|x| x.layer + 1).max().unwrap_or(0);
+        let n_rows = extents().map(|x| x.row + 1).max().unwrap_or(0);
+        let n_cols = extents().map(|x| x.col_end).max().unwrap_or(0);
+
+        let empty = (".".to_string(), CellKind::Empty);
+        let mut cells = vec![vec![vec![empty; n_cols]; n_rows]; n_layers];
+        let mut set = |location: Location, text: String, kind: CellKind| {
+            cells[location.layer][location.row][location.col] = (text, kind);
+        };
+
+        for (i, (extent, number)) in self.numbers.iter().enumerate() {
+            let kind = if self.adjacent_symbols(i).is_empty() {
+                CellKind::NonPartNumber
+            } else {
+                CellKind::PartNumber
+            };
+            // Pad with zeros so that we reproduce any leading zeros in the input.
+            let digits = format!(
+                "{number:0width$}",
+                width = extent.col_end - extent.col_begin
+            );
+            for (location, digit) in extent.locations().zip(digits.chars()) {
+                set(location, digit.to_string(), kind);
+            }
+        }
+
+        let gears: HashSet<Location> = rule
+            .find_gears(self)
+            .iter()
+            .map(|gear| gear.location)
+            .collect();
+        for (extent, text) in &self.symbols {
+            let kind = if gears.contains(&extent.start()) {
+                CellKind::Gear
+            } else {
+                CellKind::Symbol
+            };
+            for location in extent.locations() {
+                let text = if location == extent.start() {
+                    text.clone()
+                } else {
+                    String::new()
+                };
+                set(location, text, kind);
+            }
+        }
+
+        cells
     }
-    if (location.row + 1) < extent.row {
-        return false;
+
+    // Render every run of locations of the same kind with `render_run`. Rows are separated
+    // by newlines, and layers by blank lines.
+    fn render(&self, rule: &GearRule, render_run: impl Fn(&str, CellKind) -> String) -> String {
+        self.classify(rule)
+            .iter()
+            .map(|grid| {
+                grid.iter()
+                    .map(|row| {
+                        row.chunk_by(|(_, a), (_, b)| a == b)
+                            .map(|run| {
+                                let text: String = run.iter().map(|(x, _)| x.as_str()).collect();
+                                render_run(&text, run[0].1)
+                            })
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n")
     }
 
-    // NOTE: that we include equality in this case because
-    //  `col_end` is a non-inclusive upper bound (i.e. indicates the column
-    //  after the number has finished.)
-    if one_before(location.col) >= extent.col_end {
-        return false;
+    // Render with terminal colours, highlighting the gears given by `rule`.
+    fn render_ansi(&self, rule: &GearRule) -> String {
+        self.render(rule, |text, kind| match kind.ansi_style() {
+            Some(style) => format!("{style}{text}\x1b[0m"),
+            None => text.to_string(),
+        })
     }
-    if (location.col + 1) < extent.col_begin {
-        return false;
+
+    // Render as a standalone HTML fragment, highlighting the gears given by `rule`.
+    fn render_html(&self, rule: &GearRule) -> String {
+        let body = self.render(rule, |text, kind| {
+            let text = escape_html(text);
+            match kind.html_class() {
+                Some(class) => format!("<span class=\"{class}\">{text}</span>"),
+                None => text,
+            }
+        });
+        format!("{HTML_STYLE}\n<pre class=\"schematic\">{body}</pre>")
     }
+}
 
-    true
+fn escape_html(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            _ => result.push(c),
+        }
+    }
+    result
 }
 
-fn part2(input: &str) -> String {
-    // Parse the schematic as for part 1.
-    let schematic: Schematic = input.parse().unwrap();
+// The bipartite graph between numbers and the symbols that they touch.
+#[cfg_attr(not(test), expect(dead_code))]
+mod graph {
+    use std::fmt::Write;
 
-    // Now we need to identify any 'gears'; that is a '*' which has exactly two numbers
-    // adjacent to it.
-    let answer: u32 = schematic
-        .symbols
-        .iter()
-        .filter(|&&(_, c)| c == '*')
-        .filter_map(|(location, _)| -> Option<u32> {
-            // At this point we have the location of a potential gear symbol.
-            // We hope to find exactly two adjacent numbers...
-            // PERF:  We really need to have some form of acceleration structure to avoid an O(N)
-            //  scan over all known numbers for every symbol.
-            let adjacent_numbers: Vec<u32> = schematic
-                .numbers
+    use crate::Schematic;
+
+    // A node in the graph; the index is into `Schematic::numbers` or `Schematic::symbols`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub(crate) enum Node {
+        Number(usize),
+        Symbol(usize),
+    }
+
+    pub(crate) struct AdjacencyGraph<'a> {
+        schematic: &'a Schematic,
+        // For every number, the symbols that it touches.
+        number_edges: Vec<Vec<usize>>,
+        // For every symbol, the numbers that it touches.
+        symbol_edges: Vec<Vec<usize>>,
+    }
+
+    impl<'a> AdjacencyGraph<'a> {
+        pub(crate) fn new(schematic: &'a Schematic) -> Self {
+            Self {
+                schematic,
+                number_edges: (0..schematic.numbers.len())
+                    .map(|i| schematic.adjacent_symbols(i))
+                    .collect(),
+                symbol_edges: (0..schematic.symbols.len())
+                    .map(|i| schematic.adjacent_numbers(i))
+                    .collect(),
+            }
+        }
+
+        fn neighbours(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
+            let (edges, to_node): (&[usize], fn(usize) -> Node) = match node {
+                Node::Number(i) => (&self.number_edges[i], Node::Symbol),
+                Node::Symbol(i) => (&self.symbol_edges[i], Node::Number),
+            };
+            edges.iter().map(move |&i| to_node(i))
+        }
+
+        // The connected components of the graph. Each component is sorted, and the
+        // components are ordered by their first node.
+        pub(crate) fn components(&self) -> Vec<Vec<Node>> {
+            let mut seen_numbers = vec![false; self.number_edges.len()];
+            let mut seen_symbols = vec![false; self.symbol_edges.len()];
+            let mut components: Vec<Vec<Node>> = Vec::new();
+
+            let starts = (0..self.number_edges.len())
+                .map(Node::Number)
+                .chain((0..self.symbol_edges.len()).map(Node::Symbol));
+            for start in starts {
+                let mut stack: Vec<Node> = vec![start];
+                let mut component: Vec<Node> = Vec::new();
+                while let Some(node) = stack.pop() {
+                    let seen = match node {
+                        Node::Number(i) => &mut seen_numbers[i],
+                        Node::Symbol(i) => &mut seen_symbols[i],
+                    };
+                    if *seen {
+                        continue;
+                    }
+                    *seen = true;
+                    component.push(node);
+                    stack.extend(self.neighbours(node));
+                }
+                if !component.is_empty() {
+                    component.sort_unstable();
+                    components.push(component);
+                }
+            }
+
+            components.sort_unstable();
+            components
+        }
+
+        // Numbers that touch more than one symbol.
+        pub(crate) fn shared_numbers(&self) -> Vec<usize> {
+            (0..self.number_edges.len())
+                .filter(|&i| self.number_edges[i].len() > 1)
+                .collect()
+        }
+
+        // Symbols that don't touch any numbers.
+        pub(crate) fn isolated_symbols(&self) -> Vec<usize> {
+            (0..self.symbol_edges.len())
+                .filter(|&i| self.symbol_edges[i].is_empty())
+                .collect()
+        }
+
+        // Numbers that don't touch any symbols; i.e. those that are not part numbers.
+        pub(crate) fn isolated_numbers(&self) -> Vec<usize> {
+            (0..self.number_edges.len())
+                .filter(|&i| self.number_edges[i].is_empty())
+                .collect()
+        }
+
+        // Export in the Graphviz DOT language. Numbers are drawn as ellipses, and symbols
+        // as boxes.
+        pub(crate) fn to_dot(&self) -> String {
+            let mut dot = String::from("graph schematic {\n");
+            for (i, (extent, number)) in self.schematic.numbers.iter().enumerate() {
+                writeln!(
+                    dot,
+                    "  n{i} [label=\"{number}\" tooltip=\"layer {} row {} col {}\"];",
+                    extent.layer, extent.row, extent.col_begin
+                )
+                .unwrap();
+            }
+            for (i, (extent, symbol)) in self.schematic.symbols.iter().enumerate() {
+                // Only these two characters need escaping inside a quoted DOT string.
+                let label = symbol.replace('\\', "\\\\").replace('"', "\\\"");
+                writeln!(
+                    dot,
+                    "  s{i} [label=\"{label}\" shape=box tooltip=\"layer {} row {} col {}\"];",
+                    extent.layer, extent.row, extent.col_begin
+                )
+                .unwrap();
+            }
+            for (i, symbols) in self.number_edges.iter().enumerate() {
+                for j in symbols {
+                    writeln!(dot, "  n{i} -- s{j};").unwrap();
+                }
+            }
+            dot.push_str("}\n");
+            dot
+        }
+    }
+}
+
+// A schematic that can be changed one cell at a time.
+#[cfg_attr(not(test), expect(dead_code))]
+mod editable {
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
+    use std::ops::Range;
+
+    use crate::{Extent, Location, Schematic};
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) struct EditSchematicError;
+
+    // Keeps the sum of part numbers and the sum of gear ratios up to date as cells are
+    // edited, only revisiting the numbers and symbols near the edited cell.
+    pub(crate) struct EditableSchematic {
+        // The contents of every location that isn't '.'.
+        cells: HashMap<Location, char>,
+
+        // Numbers are keyed by an id that doesn't change as other numbers come and go.
+        numbers: HashMap<usize, (Extent, u32)>,
+        number_at: HashMap<Location, usize>,
+        next_number_id: usize,
+
+        part_number_sum: u64,
+        gear_ratio_sum: u64,
+    }
+
+    fn is_symbol(c: char) -> bool {
+        c != '.' && !c.is_ascii_digit()
+    }
+
+    impl EditableSchematic {
+        // Fails if the schematic couldn't have come from the default parsing options, e.g.
+        // if it has negative numbers or symbols that are wider than one column.
+        pub(crate) fn new(schematic: &Schematic) -> Result<Self, EditSchematicError> {
+            let mut result = Self {
+                cells: HashMap::new(),
+                numbers: HashMap::new(),
+                number_at: HashMap::new(),
+                next_number_id: 0,
+                part_number_sum: 0,
+                gear_ratio_sum: 0,
+            };
+
+            for (extent, number) in &schematic.numbers {
+                let number = u32::try_from(*number).map_err(|_| EditSchematicError)?;
+                // Pad with zeros so that we reproduce any leading zeros in the input.
+                let digits = format!(
+                    "{number:0width$}",
+                    width = extent.col_end - extent.col_begin
+                );
+                for (location, digit) in extent.locations().zip(digits.chars()) {
+                    result.cells.insert(location, digit);
+                }
+                result.add_number(*extent, number);
+            }
+            for (extent, text) in &schematic.symbols {
+                let mut chars = text.chars();
+                let symbol = chars.next().ok_or(EditSchematicError)?;
+                if chars.next().is_some() || extent.col_end != extent.col_begin + 1 {
+                    return Err(EditSchematicError);
+                }
+                result.cells.insert(extent.start(), symbol);
+            }
+
+            let ids: Vec<usize> = result.numbers.keys().copied().collect();
+            result.part_number_sum = ids.iter().map(|&id| result.part_contribution(id)).sum();
+            result.gear_ratio_sum = schematic
+                .symbols
                 .iter()
-                .filter(|(extent, _)| is_adjacent(extent, location))
-                .map(|&(_, number)| number)
+                .map(|(extent, _)| result.gear_contribution(extent.start()))
+                .sum();
+            Ok(result)
+        }
+
+        pub(crate) fn part_number_sum(&self) -> u64 {
+            self.part_number_sum
+        }
+
+        pub(crate) fn gear_ratio_sum(&self) -> u64 {
+            self.gear_ratio_sum
+        }
+
+        // Set the given location to a digit, '.', or a symbol. Numbers on the same row are
+        // split or merged as required. Locations in other layers can also be set.
+        pub(crate) fn set_cell(
+            &mut self,
+            location: Location,
+            c: char,
+        ) -> Result<(), EditSchematicError> {
+            if c.is_whitespace() || c.is_control() {
+                return Err(EditSchematicError);
+            }
+
+            // The numbers that could be split or merged are those touching this cell on the
+            // same row; together with the cell they span every digit that can be affected.
+            let Location { layer, row, .. } = location;
+            let mut old_ids: Vec<usize> = (location.col.saturating_sub(1)..=(location.col + 1))
+                .filter_map(|col| self.number_at.get(&Location { layer, row, col }).copied())
                 .collect();
-            if adjacent_numbers.len() == 2 {
-                // This is a gear!
-                Some(adjacent_numbers.iter().product::<u32>())
+            old_ids.dedup();
+            let span = old_ids
+                .iter()
+                .map(|id| {
+                    let (extent, _) = &self.numbers[id];
+                    extent.col_begin..extent.col_end
+                })
+                .fold(location.col..(location.col + 1), |a, b| {
+                    a.start.min(b.start)..a.end.max(b.end)
+                });
+
+            // Work out the new numbers before changing anything, so that we can fail cleanly.
+            let new_numbers = self.digit_runs(layer, row, span, (location, c))?;
+
+            // Everything whose contribution might change: numbers that are being replaced or
+            // whose neighbour is changing, and '*'s next to this cell or to a number that is
+            // being replaced.
+            let mut dirty_ids: HashSet<usize> = old_ids.iter().copied().collect();
+            dirty_ids.extend(self.adjacent_number_ids(location));
+            let mut dirty_gears: HashSet<Location> = HashSet::from([location]);
+            dirty_gears.extend(self.adjacent_stars(Extent::cell(location)));
+            for id in &old_ids {
+                dirty_gears.extend(self.adjacent_stars(self.numbers[id].0));
+            }
+
+            for &id in &dirty_ids {
+                self.part_number_sum -= self.part_contribution(id);
+            }
+            for &x in &dirty_gears {
+                self.gear_ratio_sum -= self.gear_contribution(x);
+            }
+
+            // Apply the edit.
+            for id in old_ids {
+                self.remove_number(id);
+            }
+            if c == '.' {
+                self.cells.remove(&location);
             } else {
-                // Incorrect number of adjacent numbers.. not a gear.
-                None
+                self.cells.insert(location, c);
+            }
+            for (extent, number) in new_numbers {
+                dirty_gears.extend(self.adjacent_stars(extent));
+                dirty_ids.insert(self.add_number(extent, number));
             }
-        })
-        .sum();
 
-    answer.to_string()
+            for &id in &dirty_ids {
+                if self.numbers.contains_key(&id) {
+                    self.part_number_sum += self.part_contribution(id);
+                }
+            }
+            for &x in &dirty_gears {
+                self.gear_ratio_sum += self.gear_contribution(x);
+            }
+
+            Ok(())
+        }
+
+        // The numbers formed by the digits within `cols` on the given row, if the cell at
+        // `replacement.0` were set to `replacement.1`.
+        fn digit_runs(
+            &self,
+            layer: usize,
+            row: usize,
+            cols: Range<usize>,
+            replacement: (Location, char),
+        ) -> Result<Vec<(Extent, u32)>, EditSchematicError> {
+            let get = |location: Location| {
+                if location == replacement.0 {
+                    Some(replacement.1)
+                } else {
+                    self.cells.get(&location).copied()
+                }
+            };
+
+            let mut result: Vec<(Extent, u32)> = Vec::new();
+            let mut current: Option<(usize, String)> = None;
+            for col in cols.start..=cols.end {
+                // NOTE: We go one past the end so that the last number is always flushed.
+                let c = if col < cols.end {
+                    get(Location { layer, row, col })
+                } else {
+                    None
+                };
+                match (c, &mut current) {
+                    (Some(d), Some((_, digits))) if d.is_ascii_digit() => digits.push(d),
+                    (Some(d), None) if d.is_ascii_digit() => current = Some((col, d.to_string())),
+                    (_, _) => {
+                        if let Some((col_begin, digits)) = current.take() {
+                            let number = digits.parse().map_err(|_| EditSchematicError)?;
+                            let extent = Extent {
+                                layer,
+                                row,
+                                col_begin,
+                                col_end: col,
+                            };
+                            result.push((extent, number));
+                        }
+                    }
+                }
+            }
+            Ok(result)
+        }
+
+        fn add_number(&mut self, extent: Extent, number: u32) -> usize {
+            let id = self.next_number_id;
+            self.next_number_id += 1;
+            for location in extent.locations() {
+                self.number_at.insert(location, id);
+            }
+            self.numbers.insert(id, (extent, number));
+            id
+        }
+
+        fn remove_number(&mut self, id: usize) {
+            let (extent, _) = self.numbers.remove(&id).unwrap();
+            for location in extent.locations() {
+                self.number_at.remove(&location);
+            }
+        }
+
+        fn adjacent_number_ids(&self, location: Location) -> Vec<usize> {
+            let mut result: Vec<usize> = Extent::cell(location)
+                .neighbours()
+                .filter_map(|x| self.number_at.get(&x).copied())
+                .collect();
+            result.sort_unstable();
+            result.dedup();
+            result
+        }
+
+        fn adjacent_stars(&self, extent: Extent) -> impl Iterator<Item = Location> + '_ {
+            extent
+                .neighbours()
+                .filter(|x| self.cells.get(x) == Some(&'*'))
+        }
+
+        // The amount that this number adds to the sum of part numbers.
+        fn part_contribution(&self, id: usize) -> u64 {
+            let (extent, number) = &self.numbers[&id];
+            let is_part = extent
+                .neighbours()
+                .any(|x| self.cells.get(&x).is_some_and(|&c| is_symbol(c)));
+            if is_part {
+                u64::from(*number)
+            } else {
+                0
+            }
+        }
+
+        // The amount that the symbol at this location adds to the sum of gear ratios.
+        fn gear_contribution(&self, location: Location) -> u64 {
+            if self.cells.get(&location) != Some(&'*') {
+                return 0;
+            }
+            let ids = self.adjacent_number_ids(location);
+            if ids.len() == 2 {
+                ids.iter().map(|id| u64::from(self.numbers[id].1)).product()
+            } else {
+                0
+            }
+        }
+    }
+
+    impl fmt::Display for EditableSchematic {
+        // Layers, if there are more than one, are separated by blank lines.
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let n_layers = self.cells.keys().map(|x| x.layer + 1).max().unwrap_or(0);
+            let n_rows = self.cells.keys().map(|x| x.row + 1).max().unwrap_or(0);
+            let n_cols = self.cells.keys().map(|x| x.col + 1).max().unwrap_or(0);
+            for layer in 0..n_layers {
+                if layer > 0 {
+                    write!(f, "\n\n")?;
+                }
+                for row in 0..n_rows {
+                    if row > 0 {
+                        writeln!(f)?;
+                    }
+                    for col in 0..n_cols {
+                        let location = Location { layer, row, col };
+                        write!(f, "{}", self.cells.get(&location).unwrap_or(&'.'))?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
 }
 
 fn main() {
     let input = get_input();
     println!("Part1: {}", part1(input));
     println!("Part2: {}", part2(input));
+
+    // Optionally draw the schematic, so that we can check the answers by eye.
+    match env::args().nth(1).as_deref() {
+        None => {}
+        Some("ansi") => {
+            let schematic: Schematic = input.parse().unwrap();
+            println!("{}", schematic.render_ansi(&GearRule::puzzle()));
+        }
+        Some("html") => {
+            let schematic: Schematic = input.parse().unwrap();
+            println!("{}", schematic.render_html(&GearRule::puzzle()));
+        }
+        Some(x) => panic!("Unknown render format '{x}'; expected 'ansi' or 'html'."),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{part1, part2};
+    use std::collections::HashSet;
+
+    use crate::editable::{EditSchematicError, EditableSchematic};
+    use crate::graph::{AdjacencyGraph, Node};
+    use crate::{
+        part1, part2, ColumnMode, Gear, GearRule, GearValue, Location, NeighbourCount,
+        ParseOptions, Schematic,
+    };
 
     const EXAMPLE: &str = "
 467..114..
@@ -269,6 +1077,369 @@ mod tests {
 ...$.*....
 .664.598..";
 
+    #[test]
+    fn adjacency_queries() {
+        let schematic: Schematic = EXAMPLE.parse().unwrap();
+
+        // The '*' at (1, 3) touches 467 and 35.
+        let i_star = schematic.symbol_at[&Location {
+            layer: 0,
+            row: 1,
+            col: 3,
+        }];
+        let values: Vec<i64> = schematic
+            .adjacent_numbers(i_star)
+            .iter()
+            .map(|&i| schematic.numbers[i].1)
+            .collect();
+        assert_eq!(values, [467, 35]);
+
+        // 114 and 58 touch no symbols; 617 touches only the '*' after it.
+        let i_114 = schematic.number_at[&Location {
+            layer: 0,
+            row: 0,
+            col: 6,
+        }];
+        assert!(schematic.adjacent_symbols(i_114).is_empty());
+        let i_617 = schematic.number_at[&Location {
+            layer: 0,
+            row: 4,
+            col: 2,
+        }];
+        let symbols: Vec<&str> = schematic
+            .adjacent_symbols(i_617)
+            .iter()
+            .map(|&i| schematic.symbols[i].1.as_str())
+            .collect();
+        assert_eq!(symbols, ["*"]);
+    }
+
+    #[test]
+    fn gear_rules() {
+        let schematic: Schematic = EXAMPLE.parse().unwrap();
+
+        let gears = GearRule::puzzle().find_gears(&schematic);
+        assert_eq!(gears.len(), 2);
+        assert_eq!(
+            gears[0],
+            Gear {
+                location: Location {
+                    layer: 0,
+                    row: 1,
+                    col: 3
+                },
+                symbol: '*',
+                numbers: vec![0, 2],
+                value: 467 * 35,
+            }
+        );
+
+        // Any symbol touching at least one number, valued by its largest neighbour.
+        let all_symbols: HashSet<char> = schematic
+            .symbols
+            .iter()
+            .flat_map(|(_, text)| text.chars())
+            .collect();
+        let rule = GearRule {
+            symbols: all_symbols.clone(),
+            neighbours: NeighbourCount::AtLeast(1),
+            value: GearValue::Max,
+        };
+        let values: Vec<i64> = rule
+            .find_gears(&schematic)
+            .iter()
+            .map(|x| x.value)
+            .collect();
+        assert_eq!(values, [467, 633, 617, 592, 664, 755]);
+
+        let rule = GearRule {
+            symbols: all_symbols,
+            neighbours: NeighbourCount::Between(1..=1),
+            value: GearValue::Sum,
+        };
+        let locations: Vec<Location> = rule
+            .find_gears(&schematic)
+            .iter()
+            .map(|x| x.location)
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                Location {
+                    layer: 0,
+                    row: 3,
+                    col: 6
+                },
+                Location {
+                    layer: 0,
+                    row: 4,
+                    col: 3
+                },
+                Location {
+                    layer: 0,
+                    row: 5,
+                    col: 5
+                },
+                Location {
+                    layer: 0,
+                    row: 8,
+                    col: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn render() {
+        let schematic: Schematic = "12*.\n..34\n&.05".parse().unwrap();
+        let rule = GearRule::puzzle();
+
+        assert_eq!(
+            schematic.render_ansi(&rule),
+            [
+                "\x1b[32m12\x1b[0m\x1b[1;35m*\x1b[0m.",
+                "..\x1b[32m34\x1b[0m",
+                "\x1b[33m&\x1b[0m.\x1b[31m05\x1b[0m",
+            ]
+            .join("\n")
+        );
+
+        let html = schematic.render_html(&rule);
+        assert!(html.ends_with(
+            "<pre class=\"schematic\">\
+            <span class=\"part\">12</span><span class=\"gear\">*</span>.\n\
+            ..<span class=\"part\">34</span>\n\
+            <span class=\"symbol\">&amp;</span>.<span class=\"non-part\">05</span></pre>"
+        ));
+    }
+
+    #[test]
+    fn adjacency_graph() {
+        let schematic: Schematic = "1.2\n*.&\n3.%\n..\"4".parse().unwrap();
+        let graph = AdjacencyGraph::new(&schematic);
+
+        assert_eq!(
+            graph.components(),
+            [
+                vec![Node::Number(0), Node::Number(2), Node::Symbol(0)],
+                vec![Node::Number(1), Node::Symbol(1)],
+                vec![Node::Number(3), Node::Symbol(2), Node::Symbol(3)],
+            ]
+        );
+        assert_eq!(graph.shared_numbers(), [3]);
+        assert!(graph.isolated_symbols().is_empty());
+        assert!(graph.isolated_numbers().is_empty());
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("graph schematic {\n"));
+        assert!(dot.contains("  s3 [label=\"\\\"\" shape=box tooltip=\"layer 0 row 3 col 2\"];\n"));
+        assert!(dot.contains("  n3 -- s2;\n  n3 -- s3;\n"));
+
+        let example: Schematic = EXAMPLE.parse().unwrap();
+        let graph = AdjacencyGraph::new(&example);
+        let isolated: Vec<i64> = graph
+            .isolated_numbers()
+            .iter()
+            .map(|&i| example.numbers[i].1)
+            .collect();
+        assert_eq!(isolated, [114, 58]);
+        assert_eq!(graph.components().len(), 6 + 2);
+    }
+
+    #[test]
+    fn incremental_edits() {
+        let schematic: Schematic = EXAMPLE.parse().unwrap();
+        let mut editable = EditableSchematic::new(&schematic).unwrap();
+        assert_eq!(editable.part_number_sum(), 4361);
+        assert_eq!(editable.gear_ratio_sum(), 467_835);
+
+        let edits = [
+            // Merge "467" with a new digit, then split it in two.
+            (0, 3, '7'),
+            (0, 1, '.'),
+            // Make "114" a part number.
+            (0, 5, '#'),
+            // Remove a gear, and then make a new one from an existing symbol.
+            (1, 3, '.'),
+            (8, 3, '*'),
+            // Join "617" to the '*' after it, replacing the symbol.
+            (4, 3, '1'),
+            // Join "58" and "755" into a gear with a new symbol.
+            (6, 7, '*'),
+            // Add a third number next to the gear between "755" and "598".
+            (8, 4, '2'),
+            // Extend the grid.
+            (10, 12, '3'),
+            (9, 12, '*'),
+        ];
+
+        for (row, col, c) in edits {
+            editable
+                .set_cell(Location { layer: 0, row, col }, c)
+                .unwrap();
+            let text = editable.to_string();
+            assert_eq!(editable.part_number_sum().to_string(), part1(&text));
+            assert_eq!(editable.gear_ratio_sum().to_string(), part2(&text));
+        }
+
+        let origin = Location {
+            layer: 0,
+            row: 0,
+            col: 0,
+        };
+        assert_eq!(editable.set_cell(origin, ' '), Err(EditSchematicError));
+
+        // A new digit next to a '*' that already has two numbers.
+        let schematic: Schematic = "2.3\n.*.\n...".parse().unwrap();
+        let mut editable = EditableSchematic::new(&schematic).unwrap();
+        assert_eq!(editable.gear_ratio_sum(), 6);
+        let below = Location {
+            layer: 0,
+            row: 2,
+            col: 1,
+        };
+        editable.set_cell(below, '5').unwrap();
+        assert_eq!(editable.gear_ratio_sum(), 0);
+
+        // A failed edit leaves everything unchanged.
+        let schematic: Schematic = "4294967295.\n.........*".parse().unwrap();
+        let mut editable = EditableSchematic::new(&schematic).unwrap();
+        let end = Location {
+            layer: 0,
+            row: 0,
+            col: 10,
+        };
+        assert_eq!(editable.set_cell(end, '0'), Err(EditSchematicError));
+        assert_eq!(editable.part_number_sum(), 4_294_967_295);
+        assert_eq!(editable.to_string(), "4294967295\n.........*");
+    }
+
+    // Summarise the parsed numbers as (row, col_begin, col_end, value).
+    fn number_tokens(schematic: &Schematic) -> Vec<(usize, usize, usize, i64)> {
+        schematic
+            .numbers
+            .iter()
+            .map(|(x, number)| (x.row, x.col_begin, x.col_end, *number))
+            .collect()
+    }
+
+    // Summarise the parsed symbols as (row, col_begin, col_end, text).
+    fn symbol_tokens(schematic: &Schematic) -> Vec<(usize, usize, usize, &str)> {
+        schematic
+            .symbols
+            .iter()
+            .map(|(x, text)| (x.row, x.col_begin, x.col_end, text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_options() {
+        let default = ParseOptions::default();
+
+        // By default, '-' is always a symbol.
+        let schematic = Schematic::parse_with("5-3 -7", &default).unwrap();
+        assert_eq!(
+            number_tokens(&schematic),
+            [(0, 0, 1, 5), (0, 2, 3, 3), (0, 5, 6, 7)]
+        );
+        assert_eq!(
+            symbol_tokens(&schematic),
+            [(0, 1, 2, "-"), (0, 3, 4, " "), (0, 4, 5, "-")]
+        );
+
+        let options = ParseOptions {
+            separators: HashSet::from(['.', ' ']),
+            negative_numbers: true,
+            ..default.clone()
+        };
+        let schematic = Schematic::parse_with("5-3 -7-\n-..*..", &options).unwrap();
+        assert_eq!(
+            number_tokens(&schematic),
+            [(0, 0, 1, 5), (0, 1, 3, -3), (0, 4, 6, -7)]
+        );
+        assert_eq!(
+            symbol_tokens(&schematic),
+            [(0, 6, 7, "-"), (1, 0, 1, "-"), (1, 3, 4, "*")]
+        );
+        assert_eq!(GearRule::puzzle().find_gears(&schematic)[0].value, 21);
+
+        // Combining accents take up columns of their own unless we count graphemes.
+        let input = "#\u{301}\u{301}.\n...4";
+        let schematic = Schematic::parse_with(input, &default).unwrap();
+        assert_eq!(schematic.symbols.len(), 3);
+        assert_eq!(schematic.adjacent_symbols(0), [2]);
+
+        let options = ParseOptions {
+            columns: ColumnMode::Graphemes,
+            ..default.clone()
+        };
+        let schematic = Schematic::parse_with(input, &options).unwrap();
+        assert_eq!(symbol_tokens(&schematic), [(0, 0, 1, "#\u{301}\u{301}")]);
+        assert!(schematic.adjacent_symbols(0).is_empty());
+
+        // Wide symbols take up two columns when we count display width.
+        let input = "\u{1F600}..\n..7";
+        let schematic = Schematic::parse_with(input, &default).unwrap();
+        assert!(schematic.adjacent_symbols(0).is_empty());
+
+        let options = ParseOptions {
+            columns: ColumnMode::DisplayWidth,
+            ..default
+        };
+        let schematic = Schematic::parse_with(input, &options).unwrap();
+        assert_eq!(symbol_tokens(&schematic), [(0, 0, 2, "\u{1F600}")]);
+        assert_eq!(schematic.adjacent_symbols(0), [0]);
+        assert_eq!(schematic.adjacent_numbers(0), [0]);
+    }
+
+    #[test]
+    fn layered() {
+        let input = "
+12..
+....
+
+.*..
+....
+
+..3.
+...9";
+        let options = ParseOptions {
+            layered: true,
+            ..ParseOptions::default()
+        };
+        let schematic = Schematic::parse_with(input, &options).unwrap();
+        let layers: Vec<usize> = schematic.numbers.iter().map(|(x, _)| x.layer).collect();
+        assert_eq!(layers, [0, 2, 2]);
+
+        // The '*' in the middle layer touches the numbers above and below it, but not the 9.
+        assert_eq!(schematic.part_number_sum(), 15);
+        let gears = GearRule::puzzle().find_gears(&schematic);
+        assert_eq!(gears.len(), 1);
+        assert_eq!(
+            gears[0].location,
+            Location {
+                layer: 1,
+                row: 0,
+                col: 1
+            }
+        );
+        assert_eq!(gears[0].value, 36);
+
+        assert_eq!(
+            schematic.render_ansi(&GearRule::puzzle()),
+            [
+                "\x1b[32m12\x1b[0m..\n....",
+                ".\x1b[1;35m*\x1b[0m..\n....",
+                "..\x1b[32m3\x1b[0m.\n...\x1b[31m9\x1b[0m",
+            ]
+            .join("\n\n")
+        );
+
+        // Without layers, the blank lines are just empty rows.
+        let flat: Schematic = input.parse().unwrap();
+        assert_eq!(flat.part_number_sum(), 0);
+    }
+
     #[test]
     fn example_part1() {
         assert_eq!(part1(EXAMPLE), "4361");
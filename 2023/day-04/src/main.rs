@@ -1,46 +1,194 @@
-use std::collections::HashSet;
-use std::num::ParseIntError;
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::{self, Write};
+use std::io::{self, BufRead};
 use std::str::FromStr;
 
 fn get_input() -> &'static str {
     include_str!("../input.txt")
 }
 
+// A set of card numbers. Numbers below `NumberSet::WIDTH` are stored as bits, so that
+// counting the numbers two sets have in common is a popcount of an AND. Larger numbers
+// are rare, so they fall back to a list, which doesn't allocate while it is empty.
+#[derive(Debug, Default)]
+struct NumberSet {
+    bits: u128,
+    overflow: Vec<u32>,
+}
+
+impl NumberSet {
+    const WIDTH: u32 = u128::BITS;
+
+    // Returns `false` if `n` was already in the set.
+    fn insert(&mut self, n: u32) -> bool {
+        if n < Self::WIDTH {
+            let bit = 1u128 << n;
+            let is_new = self.bits & bit == 0;
+            self.bits |= bit;
+            is_new
+        } else if self.overflow.contains(&n) {
+            false
+        } else {
+            self.overflow.push(n);
+            true
+        }
+    }
+
+    #[cfg_attr(not(test), expect(dead_code))]
+    fn len(&self) -> u32 {
+        self.bits.count_ones() + u32::try_from(self.overflow.len()).unwrap()
+    }
+
+    fn count_common(&self, other: &NumberSet) -> u32 {
+        let small = (self.bits & other.bits).count_ones();
+        // NOTE: This is quadratic, but the overflow lists are almost always empty.
+        let large = self
+            .overflow
+            .iter()
+            .filter(|n| other.overflow.contains(n))
+            .count();
+        small + u32::try_from(large).unwrap()
+    }
+}
+
 #[derive(Debug)]
 struct Card {
-    winning_numbers: Vec<u32>,
-    our_numbers: Vec<u32>,
+    id: u32,
+    winning_numbers: NumberSet,
+    our_numbers: NumberSet,
 }
 
-#[derive(Debug)]
-struct ParseCardError;
+#[derive(Debug, PartialEq, Eq)]
+enum ParseCardError {
+    // The line is not of the form "Card N: <numbers> | <numbers>".
+    Malformed,
+    // The same number appears twice in one list of numbers.
+    DuplicateNumber(u32),
+}
 
-fn parse_numbers(s: &str) -> Result<Vec<u32>, ParseIntError> {
-    s.split_whitespace().map(str::parse::<u32>).collect()
+impl fmt::Display for ParseCardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCardError::Malformed => {
+                write!(f, "expected a card like \"Card 1: 1 2 3 | 4 5 6\"")
+            }
+            ParseCardError::DuplicateNumber(n) => {
+                write!(f, "the number {n} appears more than once in the same list")
+            }
+        }
+    }
+}
+
+fn parse_numbers(s: &str) -> Result<NumberSet, ParseCardError> {
+    let mut numbers = NumberSet::default();
+    for n in s.split_whitespace() {
+        let n = n.parse().map_err(|_| ParseCardError::Malformed)?;
+        if !numbers.insert(n) {
+            return Err(ParseCardError::DuplicateNumber(n));
+        }
+    }
+    Ok(numbers)
 }
 
 impl FromStr for Card {
     type Err = ParseCardError;
 
     fn from_str(s: &str) -> Result<Card, ParseCardError> {
-        let contents = match s.split_once(": ") {
-            Some((_, contents)) => Ok(contents),
-            None => Err(ParseCardError),
-        }?;
+        let Some((header, contents)) = s.split_once(": ") else {
+            return Err(ParseCardError::Malformed);
+        };
+        let id = header
+            .strip_prefix("Card ")
+            .and_then(|id| id.trim().parse().ok())
+            .ok_or(ParseCardError::Malformed)?;
 
         let Some((winning_numbers_str, our_numbers_str)) = contents.split_once(" | ") else {
-            return Err(ParseCardError);
+            return Err(ParseCardError::Malformed);
         };
 
-        let winning_numbers = parse_numbers(winning_numbers_str).map_err(|_| ParseCardError)?;
-        let our_numbers = parse_numbers(our_numbers_str).map_err(|_| ParseCardError)?;
+        let winning_numbers = parse_numbers(winning_numbers_str)?;
+        let our_numbers = parse_numbers(our_numbers_str)?;
         Ok(Card {
+            id,
             winning_numbers,
             our_numbers,
         })
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum DeckErrorKind {
+    Card(ParseCardError),
+    // A card with this id has already been seen.
+    DuplicateId { id: u32, first_line: usize },
+    // The card ids skip ahead, so a card is either missing or out of order.
+    UnexpectedId { expected: u32, found: u32 },
+}
+
+// A problem with the deck as a whole, along with the (1-based) line on which it was found.
+#[derive(Debug, PartialEq, Eq)]
+struct DeckError {
+    line: usize,
+    kind: DeckErrorKind,
+}
+
+impl fmt::Display for DeckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            DeckErrorKind::Card(e) => write!(f, "{e}"),
+            DeckErrorKind::DuplicateId { id, first_line } => {
+                write!(f, "card {id} was already seen on line {first_line}")
+            }
+            DeckErrorKind::UnexpectedId { expected, found } => write!(
+                f,
+                "expected card {expected} but found card {found}; cards must be in order with no gaps"
+            ),
+        }
+    }
+}
+
+// Parses a deck one line at a time, checking that the cards are numbered 1, 2, 3, ...
+// The copy rules in part 2 depend on this order, so we don't want to silently assume it.
+#[derive(Default)]
+struct DeckParser {
+    // Entry `i` is the line on which card `i + 1` was found.
+    lines: Vec<usize>,
+}
+
+impl DeckParser {
+    // Returns `None` for blank lines.
+    fn parse_line(&mut self, line: usize, s: &str) -> Result<Option<Card>, DeckError> {
+        if s.trim().is_empty() {
+            return Ok(None);
+        }
+        let error = |kind| DeckError { line, kind };
+
+        let card: Card = s.parse().map_err(|e| error(DeckErrorKind::Card(e)))?;
+        let expected = u32::try_from(self.lines.len() + 1).unwrap();
+        let seen_on = card
+            .id
+            .checked_sub(1)
+            .and_then(|i| self.lines.get(i as usize));
+        if let Some(&first_line) = seen_on {
+            return Err(error(DeckErrorKind::DuplicateId {
+                id: card.id,
+                first_line,
+            }));
+        }
+        if card.id != expected {
+            return Err(error(DeckErrorKind::UnexpectedId {
+                expected,
+                found: card.id,
+            }));
+        }
+
+        self.lines.push(line);
+        Ok(Some(card))
+    }
+}
+
 // Some new syntax here!
 //   'a represents a lifetime that we have labelled "a".
 //   It must appear in `parse_cards<'a>` to declare the label.
@@ -58,78 +206,509 @@ impl FromStr for Card {
 // But then clippy points out that we can simplify it to the following using
 //  the placeholder lifetime `'_`. This will match the lifetime of the argument.
 fn parse_cards(input: &str) -> impl Iterator<Item = Card> + '_ {
-    input.trim().lines().map(|x| x.parse().unwrap())
+    let mut parser = DeckParser::default();
+    input.lines().enumerate().filter_map(move |(i, line)| {
+        parser
+            .parse_line(i + 1, line)
+            .unwrap_or_else(|e| panic!("{e}"))
+    })
 }
 
 fn num_winning(card: &Card) -> u32 {
-    let winning: HashSet<u32> = card.winning_numbers.iter().copied().collect();
-    let ours: HashSet<u32> = card.our_numbers.iter().copied().collect();
-    u32::try_from(ours.intersection(&winning).count()).unwrap()
+    card.winning_numbers.count_common(&card.our_numbers)
 }
 
-fn part1(input: &str) -> String {
-    let answer: u32 = parse_cards(input)
-        .map(|card| {
-            let n = num_winning(&card);
-            match n {
-                0 => 0,
-                _ => 2u32.pow(n - 1),
+// How many points a card is worth, given its number of winning numbers.
+#[derive(Clone, Copy, Debug)]
+enum Scoring {
+    // One point for the first match, then doubled for each match after that.
+    Doubling,
+    #[cfg_attr(not(test), expect(dead_code))]
+    Linear,
+    // Every card is worth one point, so the score is the number of cards.
+    Flat,
+}
+
+impl Scoring {
+    // Returns `None` on overflow.
+    fn score(self, num_winning: u32) -> Option<u128> {
+        match (self, num_winning) {
+            (Scoring::Doubling, 0) => Some(0),
+            (Scoring::Doubling, n) => 1u128.checked_shl(n - 1),
+            (Scoring::Linear, n) => Some(n.into()),
+            (Scoring::Flat, _) => Some(1),
+        }
+    }
+}
+
+// How many of the following cards a card wins copies of.
+#[derive(Clone, Copy, Debug)]
+enum CopyCount {
+    None,
+    // One following card per winning number.
+    Matches,
+    // NOTE: Without wrap-around, the memory used is proportional to this.
+    #[cfg_attr(not(test), expect(dead_code))]
+    Fixed(usize),
+}
+
+impl CopyCount {
+    fn count(self, num_winning: u32) -> usize {
+        match self {
+            CopyCount::None => 0,
+            CopyCount::Matches => usize::try_from(num_winning).unwrap(),
+            CopyCount::Fixed(n) => n,
+        }
+    }
+}
+
+// The rules of the game: every copy of every card scores points, and wins further copies of
+// the cards that follow it.
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    scoring: Scoring,
+    copies: CopyCount,
+    // Whether the cards after the last card are the cards at the start of the deck.
+    // This can create cycles of copies, which only end if there is a cap on the copies.
+    wrap_around: bool,
+    // The most copies we can hold of any one card, including the original.
+    max_copies: Option<u128>,
+}
+
+impl Rules {
+    fn part1() -> Rules {
+        Rules {
+            scoring: Scoring::Doubling,
+            copies: CopyCount::None,
+            wrap_around: false,
+            max_copies: None,
+        }
+    }
+
+    fn part2() -> Rules {
+        Rules {
+            scoring: Scoring::Flat,
+            copies: CopyCount::Matches,
+            wrap_around: false,
+            max_copies: None,
+        }
+    }
+
+    // Play a deck of cards, given the number of winning numbers on each, returning the score.
+    fn play(&self, deck: impl IntoIterator<Item = u32>) -> Result<u128, PlayError> {
+        let mut game = Game::new(self);
+        for num_winning in deck {
+            game.add_card(num_winning)?;
+        }
+        game.finish()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PlayError {
+    Overflow,
+    // Copies wrap around in a cycle that never ends, since there is no cap on the copies.
+    Endless,
+}
+
+impl From<CopyOverflowError> for PlayError {
+    fn from(_: CopyOverflowError) -> PlayError {
+        PlayError::Overflow
+    }
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayError::Overflow => write!(f, "Too many cards to count"),
+            PlayError::Endless => write!(f, "Copies wrap around forever; set a cap on copies"),
+        }
+    }
+}
+
+// A game in progress, with the cards added one at a time.
+//
+// Without wrap-around, we only need to look back at earlier cards, so cards are scored as
+// they arrive. With wrap-around, the last cards can win copies of the first ones, so we keep
+// what we need from each card and settle the whole deck at the end.
+struct Game<'a> {
+    rules: &'a Rules,
+    counter: CopyCounter,
+    score: u128,
+    // The score and number of copied cards of every card so far; only used with wrap-around.
+    deck: Vec<(u128, usize)>,
+}
+
+impl Game<'_> {
+    fn new(rules: &Rules) -> Game<'_> {
+        Game {
+            rules,
+            counter: CopyCounter {
+                max_copies: rules.max_copies,
+                ..CopyCounter::default()
+            },
+            score: 0,
+            deck: Vec::new(),
+        }
+    }
+
+    fn add_card(&mut self, num_winning: u32) -> Result<(), PlayError> {
+        let score = self
+            .rules
+            .scoring
+            .score(num_winning)
+            .ok_or(PlayError::Overflow)?;
+        let num_copied = self.rules.copies.count(num_winning);
+        if self.rules.wrap_around {
+            self.deck.push((score, num_copied));
+        } else {
+            let num_copies = self.counter.add_card(num_copied)?;
+            self.score = add_score(self.score, score, num_copies)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<u128, PlayError> {
+        if !self.rules.wrap_around {
+            return Ok(self.score);
+        }
+        let copied: Vec<usize> = self.deck.iter().map(|&(_, n)| n).collect();
+        let num_copies = count_copies(&copied, true, self.rules.max_copies)?;
+        self.deck
+            .iter()
+            .zip(num_copies)
+            .try_fold(0, |total, (&(score, _), n)| add_score(total, score, n))
+    }
+}
+
+fn add_score(total: u128, score: u128, num_copies: u128) -> Result<u128, PlayError> {
+    score
+        .checked_mul(num_copies)
+        .and_then(|score| total.checked_add(score))
+        .ok_or(PlayError::Overflow)
+}
+
+// The distinct cards that card `i` wins copies of, and how many copies it wins of each of them
+// for every copy of itself, where each card `i` copies the `copied[i]` cards after it.
+// NOTE: With wrap-around, a card can copy another more than once, if it copies more than the
+//  whole deck.
+fn copy_targets(
+    copied: &[usize],
+    i: usize,
+    wrap_around: bool,
+) -> impl Iterator<Item = (usize, u128)> + '_ {
+    let n = copied.len();
+    let k = copied[i];
+    let num_targets = if wrap_around {
+        k.min(n)
+    } else {
+        k.min(n - i - 1)
+    };
+    (1..=num_targets).map(move |t| {
+        let times = if wrap_around {
+            k / n + usize::from(t <= k % n)
+        } else {
+            1
+        };
+        ((i + t) % n, u128::try_from(times).unwrap())
+    })
+}
+
+// Count the copies of every card in a whole deck, where card `i` wins copies of the
+// `copied[i]` cards after it.
+//
+// The cards and the copies between them form a graph. Cards that aren't on or after a cycle
+// are counted in topological order. Each card on a cycle has more copies than the one
+// before it, until the cap is reached, so these and every card after them reach the cap.
+// Cycles can only happen with wrap-around.
+fn count_copies(
+    copied: &[usize],
+    wrap_around: bool,
+    max_copies: Option<u128>,
+) -> Result<Vec<u128>, PlayError> {
+    let n = copied.len();
+    let mut num_incoming = vec![0usize; n];
+    for i in 0..n {
+        for (j, _) in copy_targets(copied, i, wrap_around) {
+            num_incoming[j] += 1;
+        }
+    }
+
+    let cap = |x: u128| max_copies.map_or(x, |max| x.min(max));
+    let mut num_copies = vec![1u128; n];
+    let mut is_counted = vec![false; n];
+    let mut ready: Vec<usize> = (0..n).filter(|&i| num_incoming[i] == 0).collect();
+    while let Some(i) = ready.pop() {
+        is_counted[i] = true;
+        num_copies[i] = cap(num_copies[i]);
+        for (j, times) in copy_targets(copied, i, wrap_around) {
+            // NOTE: With a cap, overflow just means that the card will reach the cap.
+            num_copies[j] = match num_copies[i]
+                .checked_mul(times)
+                .and_then(|won| num_copies[j].checked_add(won))
+            {
+                Some(x) => x,
+                None if max_copies.is_some() => u128::MAX,
+                None => return Err(PlayError::Overflow),
+            };
+            num_incoming[j] -= 1;
+            if num_incoming[j] == 0 {
+                ready.push(j);
             }
-        })
-        .sum::<u32>();
-    answer.to_string()
+        }
+    }
+
+    for (i, x) in num_copies.iter_mut().enumerate() {
+        if !is_counted[i] {
+            *x = max_copies.ok_or(PlayError::Endless)?;
+        }
+    }
+    Ok(num_copies)
 }
 
-struct Replicator {
-    /// The number of copies that should be made.
-    num_copies: u32,
+// Where the copies of each card came from, so that we can see how copies cascade through a
+// deck. Cards are numbered from 0 here, but from 1 in the output.
+struct Provenance {
+    // The number of each card that we end up with, including the original.
+    num_copies: Vec<u128>,
+
+    // Entry `i` lists the cards that won copies of card `i`, with the number won by each.
+    // NOTE: With a cap on copies, these can add up to more copies than we keep.
+    sources: Vec<Vec<(usize, u128)>>,
 
-    /// The number of remaining cards to which this applies.
-    remaining_lifetime: u32,
+    // Entry `i` is the number of cards that follow, directly or through further copies,
+    // from one copy of card `i`. This is `u128::MAX` if that never ends, i.e. with a cycle.
+    descendants: Vec<u128>,
 }
 
-fn part2(input: &str) -> String {
-    // We keep track of a stack of replicators; each entry has a 'time-to-live',
-    // which is decremented as we go through the pack. It also indicates how many
-    // copies should be made.
-    let mut replicators: Vec<Replicator> = Vec::new();
-
-    let answer: u32 = parse_cards(input)
-        .map(|card| {
-            // First we score this card; i.e. figure out how many of it we have.
-            // One for the initial copy, and then we sum up the number of copies to make
-            let num_copies = 1 + replicators.iter().map(|x| x.num_copies).sum::<u32>();
-
-            // Update the existing replicators...
-            // PERF: An alternative design could probably reduce all these allocations.
-            replicators = replicators
+impl Provenance {
+    // The scoring in `rules` is ignored, since it doesn't affect the copies.
+    fn new(deck: &[u32], rules: &Rules) -> Result<Provenance, PlayError> {
+        let copied: Vec<usize> = deck.iter().map(|&n| rules.copies.count(n)).collect();
+        let num_copies = count_copies(&copied, rules.wrap_around, rules.max_copies)?;
+
+        let mut sources = vec![Vec::new(); deck.len()];
+        for (i, &n) in num_copies.iter().enumerate() {
+            for (j, times) in copy_targets(&copied, i, rules.wrap_around) {
+                sources[j].push((i, n.saturating_mul(times)));
+            }
+        }
+        for card_sources in &mut sources {
+            card_sources.sort_unstable();
+        }
+
+        // Walk back from the cards that don't win anything, so that every card is visited
+        // after all of the cards that it wins copies of. Cards on or before a cycle are never
+        // visited.
+        let mut num_pending: Vec<usize> = (0..deck.len())
+            .map(|i| copy_targets(&copied, i, rules.wrap_around).count())
+            .collect();
+        let mut descendants = vec![0u128; deck.len()];
+        let mut is_counted = vec![false; deck.len()];
+        let mut ready: Vec<usize> = (0..deck.len()).filter(|&i| num_pending[i] == 0).collect();
+        while let Some(j) = ready.pop() {
+            is_counted[j] = true;
+            for &(i, _) in &sources[j] {
+                let times = copy_targets(&copied, i, rules.wrap_around)
+                    .find(|&(target, _)| target == j)
+                    .map_or(0, |(_, times)| times);
+                let from_j = times.saturating_mul(descendants[j].saturating_add(1));
+                descendants[i] = descendants[i].saturating_add(from_j);
+                num_pending[i] -= 1;
+                if num_pending[i] == 0 {
+                    ready.push(i);
+                }
+            }
+        }
+        for (i, x) in descendants.iter_mut().enumerate() {
+            if !is_counted[i] {
+                *x = u128::MAX;
+            }
+        }
+
+        Ok(Provenance {
+            num_copies,
+            sources,
+            descendants,
+        })
+    }
+
+    // The number of copies that each card won of other cards directly.
+    fn won(&self) -> Vec<u128> {
+        let mut won = vec![0u128; self.num_copies.len()];
+        for &(source, n) in self.sources.iter().flatten() {
+            won[source] = won[source].saturating_add(n);
+        }
+        won
+    }
+
+    // The number of cards in the total that each card is responsible for: the copies that it
+    // won, and every copy that those went on to win in turn.
+    // NOTE: A card can be counted for more than one card, e.g. a copy of card 3 won by a copy
+    //  of card 2 is counted for card 2, and also for whichever card won that copy of card 2.
+    fn contributed(&self) -> Vec<u128> {
+        self.num_copies
+            .iter()
+            .zip(&self.descendants)
+            .map(|(&n, &d)| n.saturating_mul(d))
+            .collect()
+    }
+
+    // The `n` cards that contributed the most cards to the total, with the number of cards
+    // contributed, most first.
+    fn top_contributors(&self, n: usize) -> Vec<(usize, u128)> {
+        let mut ranked: Vec<(usize, u128)> = self.contributed().into_iter().enumerate().collect();
+        ranked.sort_by_key(|&(i, contributed)| (std::cmp::Reverse(contributed), i));
+        ranked.truncate(n);
+        ranked
+    }
+
+    fn to_table(&self) -> String {
+        let mut table = format!(
+            "{:>6} {:>12} {:>12} {:>12}  from\n",
+            "card", "copies", "won", "contributed"
+        );
+        let won = self.won();
+        let contributed = self.contributed();
+        for (i, sources) in self.sources.iter().enumerate() {
+            let from: Vec<String> = sources
                 .iter()
-                .filter_map(|x| {
-                    match x.remaining_lifetime {
-                        0 => unreachable!(), // We should never create a replicator with a lifetime of 0.
-                        1 => None,
-                        n => Some(Replicator {
-                            num_copies: x.num_copies,
-                            remaining_lifetime: n - 1,
-                        }),
-                    }
-                })
+                .map(|(source, n)| format!("{}: {n}", source + 1))
                 .collect();
+            writeln!(
+                table,
+                "{:>6} {:>12} {:>12} {:>12}  {}",
+                i + 1,
+                self.num_copies[i],
+                won[i],
+                contributed[i],
+                from.join(", ")
+            )
+            .unwrap();
+        }
+        table
+    }
 
-            // ... and add a new replicator if required.
-            match num_winning(&card) {
-                0 => {}
-                n => replicators.push(Replicator {
-                    num_copies,
-                    remaining_lifetime: n,
-                }),
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph copies {\n");
+        for (i, n) in self.num_copies.iter().enumerate() {
+            writeln!(dot, "  c{i} [label=\"Card {}\\n{n}\"];", i + 1).unwrap();
+        }
+        for (i, sources) in self.sources.iter().enumerate() {
+            for (source, n) in sources {
+                writeln!(dot, "  c{source} -> c{i} [label=\"{n}\"];").unwrap();
             }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
 
-            // We return the number of copies; we will aggregate these.
-            num_copies
-        })
-        .sum();
+// Counts how many of each card we end up with, as the cards are seen one at a time.
+//
+// Each card with `n` winning numbers adds its number of copies to each of the next `n` cards.
+// Rather than updating all `n` cards, we keep a running total of the copies that apply to
+// the current card, and remember when each contribution expires. This means that every card
+// takes O(1) amortised time.
+#[derive(Default)]
+struct CopyCounter {
+    // The number of extra copies of the current card.
+    active: u128,
 
+    // Entry `i` is the number of copies that stop applying after the `i`th card from now.
+    // This is a ring buffer; its length is bounded by the largest number of winning numbers.
+    expiring: VecDeque<u128>,
+
+    // The total number of cards seen so far, including copies.
+    total: u128,
+
+    // Any copies of a card beyond this are discarded, and don't win further copies.
+    max_copies: Option<u128>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CopyOverflowError;
+
+impl CopyCounter {
+    // Add the next card, returning how many of it we have.
+    fn add_card(&mut self, num_winning: usize) -> Result<u128, CopyOverflowError> {
+        // One for the original, and then any copies won by earlier cards.
+        let num_copies = match (self.active.checked_add(1), self.max_copies) {
+            (Some(n), max_copies) => max_copies.map_or(n, |max| n.min(max)),
+            (None, Some(max)) => max,
+            (None, None) => return Err(CopyOverflowError),
+        };
+        self.total = self
+            .total
+            .checked_add(num_copies)
+            .ok_or(CopyOverflowError)?;
+
+        // Anything that only applied up to this card no longer applies.
+        self.active -= self.expiring.pop_front().unwrap_or(0);
+
+        // ... and every copy of this card wins copies of the next `num_winning` cards.
+        if num_winning > 0 {
+            self.active = self
+                .active
+                .checked_add(num_copies)
+                .ok_or(CopyOverflowError)?;
+            if self.expiring.len() < num_winning {
+                self.expiring.resize(num_winning, 0);
+            }
+            // NOTE: This can't overflow, since it is bounded by `active`.
+            self.expiring[num_winning - 1] += num_copies;
+        }
+
+        Ok(num_copies)
+    }
+}
+
+#[derive(Debug)]
+enum PlayCardsError {
+    Io(io::Error),
+    Parse(DeckError),
+    Play(PlayError),
+}
+
+impl fmt::Display for PlayCardsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayCardsError::Io(e) => write!(f, "Could not read cards: {e}"),
+            PlayCardsError::Parse(e) => write!(f, "Could not parse cards: {e}"),
+            PlayCardsError::Play(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+// Play the cards with the given rules, reading the cards one line at a time.
+fn play_cards(mut reader: impl BufRead, rules: &Rules) -> Result<u128, PlayCardsError> {
+    let mut game = Game::new(rules);
+    let mut parser = DeckParser::default();
+    // NOTE: We reuse one buffer for every line, rather than allocating a `String` per line.
+    let mut line = String::new();
+    for i in 1.. {
+        line.clear();
+        if reader.read_line(&mut line).map_err(PlayCardsError::Io)? == 0 {
+            break;
+        }
+        let Some(card) = parser.parse_line(i, &line).map_err(PlayCardsError::Parse)? else {
+            continue;
+        };
+        game.add_card(num_winning(&card))
+            .map_err(PlayCardsError::Play)?;
+    }
+    game.finish().map_err(PlayCardsError::Play)
+}
+
+fn part1(input: &str) -> String {
+    let deck = parse_cards(input).map(|card| num_winning(&card));
+    let answer = Rules::part1().play(deck).unwrap_or_else(|e| panic!("{e}"));
+    answer.to_string()
+}
+
+fn part2(input: &str) -> String {
+    // NOTE: `&[u8]` implements `BufRead`, so we can stream from the input like any other reader.
+    let answer = play_cards(input.as_bytes(), &Rules::part2()).unwrap_or_else(|e| panic!("{e}"));
     answer.to_string()
 }
 
@@ -137,11 +716,33 @@ fn main() {
     let input = get_input();
     println!("Part1: {}", part1(input));
     println!("Part2: {}", part2(input));
+
+    // Optionally show where the copies in part 2 came from.
+    let report = env::args().nth(1);
+    if let Some(format) = report.as_deref() {
+        let deck: Vec<u32> = parse_cards(input).map(|card| num_winning(&card)).collect();
+        let provenance = Provenance::new(&deck, &Rules::part2()).unwrap_or_else(|e| panic!("{e}"));
+        match format {
+            "table" => {
+                print!("{}", provenance.to_table());
+                println!("Most cards contributed:");
+                for (i, contributed) in provenance.top_contributors(5) {
+                    println!("  card {}: {contributed}", i + 1);
+                }
+            }
+            "dot" => print!("{}", provenance.to_dot()),
+            x => panic!("Unknown report format '{x}'; expected 'table' or 'dot'."),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{part1, part2};
+    use crate::{
+        part1, part2, play_cards, CopyCount, CopyCounter, CopyOverflowError, DeckError,
+        DeckErrorKind, DeckParser, NumberSet, ParseCardError, PlayCardsError, PlayError,
+        Provenance, Rules, Scoring,
+    };
 
     const EXAMPLE: &str = "
 Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
@@ -160,4 +761,220 @@ Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11";
     fn part2_example() {
         assert_eq!(part2(EXAMPLE), "30");
     }
+
+    #[test]
+    fn copy_counter() {
+        // If every card wins copies of all later cards, the number of each card doubles.
+        let mut counter = CopyCounter::default();
+        for i in 0..128 {
+            assert_eq!(counter.add_card(200), Ok(1 << i));
+        }
+        assert_eq!(counter.total, u128::MAX);
+        assert_eq!(counter.add_card(0), Err(CopyOverflowError));
+    }
+
+    #[test]
+    fn play_cards_errors() {
+        assert_eq!(play_cards(EXAMPLE.as_bytes(), &Rules::part2()).unwrap(), 30);
+        assert!(matches!(
+            play_cards("Card 1: 1 2 | x".as_bytes(), &Rules::part2()),
+            Err(PlayCardsError::Parse(_))
+        ));
+    }
+
+    fn parse_deck(input: &str) -> Result<Vec<u32>, DeckError> {
+        let mut parser = DeckParser::default();
+        let mut ids = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            if let Some(card) = parser.parse_line(i + 1, line)? {
+                ids.push(card.id);
+            }
+        }
+        Ok(ids)
+    }
+
+    #[test]
+    fn card_ids() {
+        assert_eq!(parse_deck(EXAMPLE), Ok(vec![1, 2, 3, 4, 5, 6]));
+
+        let duplicate = "Card 1: 1 | 2\n\nCard 2: 3 | 4\nCard 1: 5 | 6";
+        let error = parse_deck(duplicate).unwrap_err();
+        assert_eq!(
+            error,
+            DeckError {
+                line: 4,
+                kind: DeckErrorKind::DuplicateId {
+                    id: 1,
+                    first_line: 1
+                }
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 4: card 1 was already seen on line 1"
+        );
+
+        let missing = "Card 1: 1 | 2\nCard 3: 3 | 4";
+        assert_eq!(
+            parse_deck(missing).unwrap_err().kind,
+            DeckErrorKind::UnexpectedId {
+                expected: 2,
+                found: 3
+            }
+        );
+
+        let zero = "Card 1: 1 | 2\nCard 0: 3 | 4";
+        assert_eq!(
+            parse_deck(zero).unwrap_err().kind,
+            DeckErrorKind::UnexpectedId {
+                expected: 2,
+                found: 0
+            }
+        );
+
+        let out_of_order = "Card 2: 1 | 2\nCard 1: 3 | 4";
+        assert_eq!(
+            parse_deck(out_of_order).unwrap_err(),
+            DeckError {
+                line: 1,
+                kind: DeckErrorKind::UnexpectedId {
+                    expected: 1,
+                    found: 2
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn card_numbers() {
+        // The same number may appear in both lists, but not twice in one list.
+        assert_eq!(parse_deck("Card 1: 1 2 | 2 1"), Ok(vec![1]));
+
+        let error = parse_deck("Card 1: 1 | 2\nCard 2: 5 7 5 | 1").unwrap_err();
+        assert_eq!(
+            error.kind,
+            DeckErrorKind::Card(ParseCardError::DuplicateNumber(5))
+        );
+        assert_eq!(error.line, 2);
+
+        assert!(matches!(
+            play_cards(
+                "\nCard 1: 1 2 | 3\nCard 2 1 | 2".as_bytes(),
+                &Rules::part2()
+            ),
+            Err(PlayCardsError::Parse(DeckError {
+                line: 3,
+                kind: DeckErrorKind::Card(ParseCardError::Malformed)
+            }))
+        ));
+    }
+
+    #[test]
+    fn number_set() {
+        let mut a = NumberSet::default();
+        let mut b = NumberSet::default();
+        for n in [0, 5, 127, 128, 1000, u32::MAX] {
+            assert!(a.insert(n));
+        }
+        assert!(!a.insert(127));
+        assert!(!a.insert(1000));
+        assert_eq!(a.len(), 6);
+
+        for n in [5, 6, 128, 999, u32::MAX] {
+            b.insert(n);
+        }
+        assert_eq!(a.count_common(&b), 3);
+        assert_eq!(b.count_common(&a), 3);
+    }
+
+    #[test]
+    fn rules() {
+        let example = [4, 2, 2, 1, 0, 0];
+        let linear = Rules {
+            scoring: Scoring::Linear,
+            ..Rules::part1()
+        };
+        assert_eq!(linear.play(example), Ok(9));
+
+        let capped = Rules {
+            max_copies: Some(2),
+            ..Rules::part2()
+        };
+        assert_eq!(capped.play(example), Ok(10));
+        assert_eq!(
+            play_cards(EXAMPLE.as_bytes(), &capped).unwrap(),
+            capped.play(example).unwrap()
+        );
+
+        let overflow = Rules::part1().play([129]);
+        assert_eq!(overflow, Err(PlayError::Overflow));
+    }
+
+    #[test]
+    fn wrap_around() {
+        let wrapping = Rules {
+            wrap_around: true,
+            ..Rules::part2()
+        };
+        // Nothing in the example wraps around.
+        assert_eq!(wrapping.play([4, 2, 2, 1, 0, 0]), Ok(30));
+
+        // Without wrap-around, the last card copies nothing.
+        assert_eq!(Rules::part2().play([0, 1]), Ok(2));
+        assert_eq!(wrapping.play([0, 1]), Ok(3));
+
+        // The two cards copy each other forever, unless there is a cap.
+        assert_eq!(wrapping.play([1, 1]), Err(PlayError::Endless));
+        let capped = Rules {
+            max_copies: Some(5),
+            ..wrapping
+        };
+        assert_eq!(capped.play([1, 1]), Ok(10));
+        // The third card isn't on the cycle, but it comes after it.
+        assert_eq!(capped.play([1, 2, 0]), Ok(15));
+        // Copies wrap around here, but never in a cycle, so there is no need for a cap.
+        assert_eq!(wrapping.play([0, 1, 1]), Ok(6));
+
+        // Each card copies the whole deck, including itself, and then the next card again.
+        let fixed = Rules {
+            copies: CopyCount::Fixed(3),
+            ..capped
+        };
+        assert_eq!(fixed.play([0, 0]), Ok(10));
+    }
+
+    #[test]
+    fn provenance() {
+        let provenance = Provenance::new(&[4, 2, 2, 1, 0, 0], &Rules::part2()).unwrap();
+        assert_eq!(provenance.num_copies, [1, 2, 4, 8, 14, 1]);
+        assert_eq!(provenance.sources[4], [(0, 1), (2, 4), (3, 8)]);
+        assert_eq!(provenance.won(), [4, 4, 8, 8, 0, 0]);
+        // Card 1 wins one copy each of cards 2 to 5, and those go on to win 10 more.
+        assert_eq!(provenance.contributed(), [14, 12, 12, 8, 0, 0]);
+        assert_eq!(provenance.top_contributors(2), [(0, 14), (1, 12)]);
+
+        let table = provenance.to_table();
+        assert_eq!(
+            table
+                .lines()
+                .nth(5)
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            ["5", "14", "0", "0", "1:", "1,", "3:", "4,", "4:", "8"]
+        );
+        let dot = provenance.to_dot();
+        assert!(dot.contains("  c4 [label=\"Card 5\\n14\"];"));
+        assert!(dot.contains("  c3 -> c4 [label=\"8\"];"));
+        assert_eq!(dot.matches("->").count(), 9);
+
+        // The first two cards copy each other forever; the third card is after the cycle.
+        let capped = Rules {
+            wrap_around: true,
+            max_copies: Some(5),
+            ..Rules::part2()
+        };
+        let provenance = Provenance::new(&[1, 2, 0], &capped).unwrap();
+        assert_eq!(provenance.contributed(), [u128::MAX, u128::MAX, 0]);
+    }
 }
@@ -1,159 +1,665 @@
-use almanac::Almanac;
+use std::env;
+
+use almanac::{Almanac, SeedMode};
 
 fn get_input() -> &'static str {
     include_str!("../input.txt")
 }
 
 mod almanac {
+    use std::fmt::{self, Write};
+    use std::num::{IntErrorKind, ParseIntError};
     use std::{ops::Range, str::FromStr};
 
+    /// An entry maps the ids in `source_range` along a line, sending the first of them to
+    /// `destination_start`, and each one after that `scale` further on. That is, it maps `x`
+    /// to `destination_start + scale * (x - source_range.start)`. A negative scale reverses
+    /// the order of the ids.
+    ///
+    /// Every entry checks when it is made that all of the ids it maps to fit in an `i64`
+    /// (and are below `i64::MAX`, so that they can end a `Range`). The scale doesn't always fit
+    /// in an `i64`, and the arithmetic is done in `i128`, so mapping an id can't overflow.
+    #[derive(Clone)]
     pub struct RangeMapEntry {
         source_range: Range<i64>,
-        destination_offset: i64,
+        destination_start: i64,
+        scale: i128,
+    }
+
+    /// Narrow an id that is known to fit in an `i64`.
+    fn narrow(id: i128) -> i64 {
+        i64::try_from(id).expect("entries are checked to stay in range")
+    }
+
+    /// Round `n / d` down, for any signs of `n` and `d`.
+    fn div_floor(n: i128, d: i128) -> i128 {
+        let q = n / d;
+        if n % d != 0 && ((n < 0) != (d < 0)) {
+            q - 1
+        } else {
+            q
+        }
+    }
+
+    /// Round `n / d` up, for any signs of `n` and `d`.
+    fn div_ceil(n: i128, d: i128) -> i128 {
+        -div_floor(-n, d)
     }
 
     impl RangeMapEntry {
-        pub fn new(destination_start: i64, source_start: i64, length: i64) -> Self {
+        /// NOTE: The caller must make sure that every id in the source range maps to an id
+        ///  that fits, as described above.
+        fn with_scale(source_range: Range<i64>, destination_start: i64, scale: i128) -> Self {
+            // A single id has no direction, so we give it a scale of one, which lets it merge
+            // with its neighbours.
+            let is_single = i128::from(source_range.end) - i128::from(source_range.start) == 1;
             Self {
-                source_range: source_start..(source_start + length),
-                destination_offset: destination_start - source_start,
+                source_range,
+                destination_start,
+                scale: if is_single { 1 } else { scale },
             }
         }
 
+        /// Returns `None` if the scale is zero, or if the source or destination ids don't fit
+        /// in an `i64`.
+        pub fn new(
+            destination_start: i64,
+            source_start: i64,
+            length: i64,
+            scale: i64,
+        ) -> Option<Self> {
+            if scale == 0 {
+                return None;
+            }
+            let source_end = source_start.checked_add(length)?;
+            let entry = Self::with_scale(source_start..source_end, destination_start, scale.into());
+            if length > 0 {
+                // The ids at the ends of the source are mapped furthest out.
+                for id in [source_start, source_end - 1] {
+                    let mapped = entry.apply(id);
+                    if mapped < i128::from(i64::MIN) || mapped >= i128::from(i64::MAX) {
+                        return None;
+                    }
+                }
+            }
+            Some(entry)
+        }
+
+        fn apply(&self, id_source: i64) -> i128 {
+            // NOTE: Both the difference and the product are bounded by the size of the range
+            //  of `i64`, which is far from the limits of `i128`.
+            let steps = i128::from(id_source) - i128::from(self.source_range.start);
+            i128::from(self.destination_start) + self.scale * steps
+        }
+
         /// Lookup the given source id, and return the destination id if we can
         /// determine it from this entry.
         pub fn lookup(&self, id_source: i64) -> Option<i64> {
             if self.source_range.contains(&id_source) {
-                Some(id_source + self.destination_offset)
+                Some(narrow(self.apply(id_source)))
             } else {
                 None
             }
         }
 
-        /// Return a tuple
-        ///     mapped range       (if any)
-        ///     unmapped range(s)  (maybe empty)
-        pub fn lookup_range(&self, source: &Range<i64>) -> (Option<Range<i64>>, Vec<Range<i64>>) {
-            if (source.end <= self.source_range.start) || (source.start >= self.source_range.end) {
-                // No intersection.
-                return (None, [source.clone()].to_vec());
-            }
-
-            if (source.start < self.source_range.start) && (source.end > self.source_range.end) {
-                // Overlap to both sides.
-                return (
-                    Some(
-                        (self.source_range.start + self.destination_offset)
-                            ..(self.source_range.end + self.destination_offset),
-                    ),
-                    [
-                        source.start..self.source_range.start,
-                        self.source_range.end..source.end,
-                    ]
-                    .to_vec(),
-                );
-            }
-
-            if (source.start >= self.source_range.start) && (source.end <= self.source_range.end) {
-                // Fully contained within the map.
-                return (
-                    Some(
-                        (source.start + self.destination_offset)
-                            ..(source.end + self.destination_offset),
-                    ),
-                    Vec::new(),
-                );
-            }
-
-            // At this point we know that we either overlap over start OR we overlap over the end.
-            #[expect(clippy::single_range_in_vec_init)]
-            if source.start < self.source_range.start {
-                (
-                    Some(
-                        (self.source_range.start + self.destination_offset)
-                            ..(source.end + self.destination_offset),
-                    ),
-                    [source.start..self.source_range.start].to_vec(),
-                )
+        /// This entry, but only for the source ids in `range`, which must be within it.
+        fn restrict(&self, range: Range<i64>) -> RangeMapEntry {
+            let destination_start = narrow(self.apply(range.start));
+            RangeMapEntry::with_scale(range, destination_start, self.scale)
+        }
+
+        /// Whether `self` followed by `next` is the same line as a single entry.
+        fn continues_into(&self, next: &RangeMapEntry) -> bool {
+            self.source_range.end == next.source_range.start
+                && self.scale == next.scale
+                && self.apply(next.source_range.start) == next.destination_start.into()
+        }
+
+        fn is_identity(&self) -> bool {
+            self.scale == 1 && self.destination_start == self.source_range.start
+        }
+
+        /// The ids that the given ids from the source range map to.
+        fn image(&self, source: &Stride) -> Stride {
+            let first = narrow(self.apply(source.start));
+            let last = narrow(self.apply(source.last()));
+            let step = u64::try_from(self.scale.unsigned_abs() * u128::from(source.step))
+                .unwrap_or(u64::MAX);
+            Stride::between(first.min(last), first.max(last), step)
+        }
+
+        /// The entry that applies this entry and then `next`, for the source ids in `range`.
+        /// Every id in `range` must land in the source of `next`.
+        fn then(&self, next: &RangeMapEntry, range: Range<i64>) -> RangeMapEntry {
+            let destination_start = narrow(next.apply(narrow(self.apply(range.start))));
+            // NOTE: If there are two ids in `range`, the product is at most the distance
+            //  between where they land, so it fits. Otherwise the scale is ignored.
+            let scale = if i128::from(range.end) - i128::from(range.start) > 1 {
+                self.scale * next.scale
             } else {
-                // Only option left is to overlap to the right
-                (
-                    Some(
-                        (source.start + self.destination_offset)
-                            ..(self.source_range.end + self.destination_offset),
-                    ),
-                    [self.source_range.end..source.end].to_vec(),
-                )
-            }
+                1
+            };
+            RangeMapEntry::with_scale(range, destination_start, scale)
+        }
+
+        /// Return the source ids in this entry that map into `target`, if any.
+        pub fn preimage(&self, target: &Range<i64>) -> Option<Range<i64>> {
+            // We want the steps `n` from the source start with `low <= scale * n <= high`.
+            let low = i128::from(target.start) - i128::from(self.destination_start);
+            let high = i128::from(target.end) - 1 - i128::from(self.destination_start);
+            let (first, last) = if self.scale > 0 {
+                (div_ceil(low, self.scale), div_floor(high, self.scale))
+            } else {
+                (div_ceil(high, self.scale), div_floor(low, self.scale))
+            };
+            let length = i128::from(self.source_range.end) - i128::from(self.source_range.start);
+            let first = first.max(0);
+            let last = last.min(length - 1);
+            (first <= last).then(|| {
+                let start = i128::from(self.source_range.start);
+                narrow(start + first)..narrow(start + last + 1)
+            })
         }
     }
 
-    #[derive(Debug)]
-    pub struct ParseRangeMapEntryErr;
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum ParseRangeMapEntryErr {
+        Malformed,
+        /// The entry's ranges don't fit in an `i64`.
+        Overflow,
+        /// The entry has a scale of zero, which would map many ids to one.
+        ZeroScale,
+    }
 
+    /// An entry is "destination source length", optionally followed by a scale, which is
+    /// one by default. For example "10 3 4 -2" maps 3, 4, 5, 6 to 10, 8, 6, 4.
     impl FromStr for RangeMapEntry {
         type Err = ParseRangeMapEntryErr;
 
         fn from_str(s: &str) -> Result<RangeMapEntry, Self::Err> {
             let parts: Vec<&str> = s.split_whitespace().collect();
+            if !(3..=4).contains(&parts.len()) {
+                return Err(ParseRangeMapEntryErr::Malformed);
+            }
 
             let get_part = |i: usize| -> Result<i64, ParseRangeMapEntryErr> {
-                parts
-                    .get(i)
-                    .ok_or(ParseRangeMapEntryErr)?
-                    .parse()
-                    .map_err(|_| ParseRangeMapEntryErr)
+                parts[i].parse().map_err(|e| match parse_int_error(&e) {
+                    ParseIntErrorKind::Overflow => ParseRangeMapEntryErr::Overflow,
+                    ParseIntErrorKind::Malformed => ParseRangeMapEntryErr::Malformed,
+                })
             };
 
             let destination_start = get_part(0)?;
             let source_start = get_part(1)?;
             let length = get_part(2)?;
-            Ok(RangeMapEntry::new(destination_start, source_start, length))
+            let scale = if parts.len() == 4 { get_part(3)? } else { 1 };
+            if scale == 0 {
+                return Err(ParseRangeMapEntryErr::ZeroScale);
+            }
+            RangeMapEntry::new(destination_start, source_start, length, scale)
+                .ok_or(ParseRangeMapEntryErr::Overflow)
         }
     }
 
+    /// The ids from `start` up to (but not including) `end`, going up in steps of `step`.
+    /// A step of one gives every id in the range, like a `Range`.
+    ///
+    /// These are the result of looking up a range, since a map that scales ids spreads them
+    /// out. We always store `end` as one past the last id, and use a step of one if there is
+    /// only one id, so that equal sets of ids compare equal.
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Stride {
+        start: i64,
+        end: i64,
+        step: u64,
+    }
+
+    impl Stride {
+        /// NOTE: `last` must be below `i64::MAX`, and `first <= last`.
+        fn between(first: i64, last: i64, step: u64) -> Stride {
+            let (last, step) = if first == last {
+                (last, 1)
+            } else {
+                // Drop anything after the last id that is a whole number of steps on.
+                let span = first.abs_diff(last);
+                (
+                    narrow(i128::from(first) + i128::from(span - span % step)),
+                    step,
+                )
+            };
+            Stride {
+                start: first,
+                end: last + 1,
+                step,
+            }
+        }
+
+        pub fn start(&self) -> i64 {
+            self.start
+        }
+
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn end(&self) -> i64 {
+            self.end
+        }
+
+        fn last(&self) -> i64 {
+            self.end - 1
+        }
+
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn contains(&self, id: i64) -> bool {
+            (self.start..self.end).contains(&id) && self.start.abs_diff(id) % self.step == 0
+        }
+
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+            let step = usize::try_from(self.step).unwrap_or(usize::MAX);
+            (self.start..self.end).step_by(step)
+        }
+
+        /// The ids that are in `range`, if any.
+        fn restrict(&self, range: &Range<i64>) -> Option<Stride> {
+            let step = i128::from(self.step);
+            let start = i128::from(self.start);
+            // Round the start of the range up to the next id, and the end down.
+            let first = start + div_ceil(i128::from(range.start) - start, step).max(0) * step;
+            let last = i128::from(self.last().min(range.end - 1));
+            (first <= last).then(|| Stride::between(narrow(first), narrow(last), self.step))
+        }
+    }
+
+    impl From<Range<i64>> for Stride {
+        /// NOTE: The range must not be empty.
+        fn from(range: Range<i64>) -> Stride {
+            Stride::between(range.start, range.end - 1, 1)
+        }
+    }
+
+    /// A stride with a step of one is the same as a range.
+    impl PartialEq<Range<i64>> for Stride {
+        fn eq(&self, range: &Range<i64>) -> bool {
+            self.step == 1 && self.start == range.start && self.end == range.end
+        }
+    }
+
+    /// Sort the strides, and merge any neighbouring ranges that overlap or touch.
+    /// Strides with a larger step are only merged when they fall within a range.
+    fn merge_strides(mut strides: Vec<Stride>) -> Vec<Stride> {
+        strides.sort();
+        strides.dedup();
+        let mut merged: Vec<Stride> = Vec::with_capacity(strides.len());
+        for stride in strides {
+            match merged.last_mut() {
+                Some(last) if last.step == 1 && stride.step == 1 && last.end >= stride.start => {
+                    last.end = last.end.max(stride.end);
+                }
+                Some(last) if last.step == 1 && last.end >= stride.end => {}
+                _ => merged.push(stride),
+            }
+        }
+        merged
+    }
+
     pub struct RangeMap {
+        /// The entries in the order that they were given, for reporting.
         entries: Vec<RangeMapEntry>,
+
+        /// The entries sorted by source, with any overlaps resolved in favour of the first
+        /// entry, entries that don't move any ids dropped, and neighbouring entries on the
+        /// same line merged. Lookups use these, so that they can use a binary search.
+        sorted: Vec<RangeMapEntry>,
     }
 
     impl RangeMap {
+        pub fn new(entries: Vec<RangeMapEntry>) -> Self {
+            let mut pieces: Vec<RangeMapEntry> = Vec::new();
+            for entry in &entries {
+                // Only the parts of this entry that no earlier entry has claimed are used.
+                let mut unclaimed = vec![entry.source_range.clone()];
+                for piece in &pieces {
+                    unclaimed = unclaimed
+                        .iter()
+                        .flat_map(|range| subtract(range, &piece.source_range))
+                        .collect();
+                }
+                pieces.extend(unclaimed.into_iter().map(|range| entry.restrict(range)));
+            }
+            pieces.retain(|piece| !piece.source_range.is_empty() && !piece.is_identity());
+            pieces.sort_by_key(|piece| piece.source_range.start);
+
+            let mut sorted: Vec<RangeMapEntry> = Vec::with_capacity(pieces.len());
+            for piece in pieces {
+                match sorted.last_mut() {
+                    Some(last) if last.continues_into(&piece) => {
+                        let scale = if last.scale == 1 {
+                            piece.scale
+                        } else {
+                            last.scale
+                        };
+                        *last = RangeMapEntry::with_scale(
+                            last.source_range.start..piece.source_range.end,
+                            last.destination_start,
+                            scale,
+                        );
+                    }
+                    _ => sorted.push(piece),
+                }
+            }
+            Self { entries, sorted }
+        }
+
+        /// The map that sends every id to itself.
+        pub fn identity() -> Self {
+            Self::new(Vec::new())
+        }
+
         pub fn lookup(&self, id_source: i64) -> i64 {
-            for entry in &self.entries {
-                if let Some(id_destination) = entry.lookup(id_source) {
-                    return id_destination;
+            let i = self
+                .sorted
+                .partition_point(|entry| entry.source_range.end <= id_source);
+            self.sorted
+                .get(i)
+                .and_then(|entry| entry.lookup(id_source))
+                .unwrap_or(id_source)
+        }
+
+        /// The sorted entries whose sources overlap `range`.
+        fn entries_within(&self, range: &Range<i64>) -> impl Iterator<Item = &RangeMapEntry> {
+            let first = self
+                .sorted
+                .partition_point(|entry| entry.source_range.end <= range.start);
+            let end = range.end;
+            self.sorted[first..]
+                .iter()
+                .take_while(move |entry| entry.source_range.start < end)
+        }
+
+        /// The parts of `range` that no entry covers, so that the map leaves them alone.
+        fn gaps(&self, range: &Range<i64>) -> Vec<Range<i64>> {
+            let mut gaps = Vec::new();
+            let mut start = range.start;
+            for entry in self.entries_within(range) {
+                if start < entry.source_range.start {
+                    gaps.push(start..entry.source_range.start);
                 }
+                start = start.max(entry.source_range.end);
+            }
+            if start < range.end {
+                gaps.push(start..range.end);
             }
-            id_source
+            gaps
         }
 
-        pub fn lookup_range(&self, source: &Range<i64>) -> Vec<Range<i64>> {
-            let mut all_unprocessed: Vec<Range<i64>> = [source.clone()].to_vec();
-            let mut all_processed: Vec<Range<i64>> = Vec::new();
+        /// Return the ids that the given ids map to, as sorted and merged strides.
+        fn lookup_stride(&self, source: &Stride) -> Vec<Stride> {
+            let range = source.start..source.end;
+            let mut strides: Vec<Stride> = self
+                .entries_within(&range)
+                .filter_map(|entry| {
+                    let ids = source.restrict(&entry.source_range)?;
+                    Some(entry.image(&ids))
+                })
+                .collect();
+            strides.extend(
+                self.gaps(&range)
+                    .iter()
+                    .filter_map(|gap| source.restrict(gap)),
+            );
+            merge_strides(strides)
+        }
+
+        /// Return the ids that `source` maps to, as sorted and merged strides. These are
+        /// plain ranges unless the map scales ids.
+        pub fn lookup_range(&self, source: &Range<i64>) -> Vec<Stride> {
+            if source.is_empty() {
+                return Vec::new();
+            }
+            self.lookup_stride(&source.clone().into())
+        }
 
-            for entry in &self.entries {
-                all_unprocessed = all_unprocessed
+        /// Return the ids that any of `sources` map to, as sorted and merged strides.
+        pub fn lookup_ranges(&self, sources: &[Stride]) -> Vec<Stride> {
+            merge_strides(
+                sources
                     .iter()
-                    .flat_map(|this_source| {
-                        let (new_processed, new_unprocessed) = entry.lookup_range(this_source);
+                    .flat_map(|source| self.lookup_stride(source))
+                    .collect(),
+            )
+        }
 
-                        if let Some(x) = new_processed {
-                            all_processed.push(x);
-                        }
+        /// Return the map that applies this map, and then `next`.
+        pub fn compose(&self, next: &RangeMap) -> RangeMap {
+            let mut entries = Vec::new();
 
-                        new_unprocessed
-                    })
-                    .collect();
+            // Ids that this map moves are then moved by `next` from where they land.
+            for entry in &self.sorted {
+                let whole = Stride::from(entry.source_range.clone());
+                let image = entry.image(&whole);
+                let mut unmoved = vec![entry.source_range.clone()];
+                for next_entry in next.entries_within(&(image.start..image.end)) {
+                    if let Some(range) = entry.preimage(&next_entry.source_range) {
+                        unmoved = unmoved
+                            .iter()
+                            .flat_map(|part| subtract(part, &range))
+                            .collect();
+                        entries.push(entry.then(next_entry, range));
+                    }
+                }
+                entries.extend(unmoved.into_iter().map(|range| entry.restrict(range)));
             }
 
-            // Any unprocessed entries at this point should be considered to be processed.
-            all_processed.extend_from_slice(&all_unprocessed);
+            // Ids that this map leaves alone are only moved by `next`.
+            for entry in &next.sorted {
+                for gap in self.gaps(&entry.source_range) {
+                    entries.push(entry.restrict(gap));
+                }
+            }
+
+            RangeMap::new(entries)
+        }
+
+        /// Return every source id that maps into `target`, as sorted and merged ranges.
+        pub fn preimage(&self, target: &Range<i64>) -> Vec<Range<i64>> {
+            let mut sources: Vec<Range<i64>> = self
+                .sorted
+                .iter()
+                .filter_map(|entry| entry.preimage(target))
+                .collect();
 
-            all_processed
+            // Ids that the map leaves alone are their own preimage.
+            sources.extend(self.gaps(target));
+            merge(sources)
         }
+
+        /// Return every source id that maps to `id_destination`, in order.
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn preimage_of(&self, id_destination: i64) -> Vec<i64> {
+            // NOTE: No range contains `i64::MAX`, so it can only be mapped to from itself.
+            let Some(end) = id_destination.checked_add(1) else {
+                return vec![id_destination];
+            };
+            self.preimage(&(id_destination..end))
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+
+        /// Report anything suspicious about the entries of this map, in the order that
+        /// they were given: entries that overlap or are empty, and gaps between entries.
+        pub fn validate(&self) -> Vec<MapIssue> {
+            let mut issues = Vec::new();
+            for (i, entry) in self.entries.iter().enumerate() {
+                let range = &entry.source_range;
+                if range.is_empty() {
+                    issues.push(MapIssue::EmptyEntry {
+                        entry: i,
+                        // NOTE: This is the length that the entry was made with, so it fits.
+                        length: range.end - range.start,
+                    });
+                }
+                // NOTE: This is quadratic, but maps only have a few dozen entries.
+                for (j, later) in self.entries.iter().enumerate().skip(i + 1) {
+                    let overlap = range.start.max(later.source_range.start)
+                        ..range.end.min(later.source_range.end);
+                    if !overlap.is_empty() {
+                        issues.push(MapIssue::Overlap {
+                            first: i,
+                            second: j,
+                            sources: overlap,
+                        });
+                    }
+                }
+            }
+
+            let covered = merge(
+                self.entries
+                    .iter()
+                    .map(|entry| entry.source_range.clone())
+                    .collect(),
+            );
+            issues.extend(
+                covered
+                    .windows(2)
+                    .map(|pair| MapIssue::Gap(pair[0].end..pair[1].start)),
+            );
+            issues
+        }
+
+        /// Parse a map, refusing any map where the answer could depend on the order of the
+        /// entries, or with entries of negative length.
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn from_str_strict(s: &str) -> Result<RangeMap, ParseRangeMapErr> {
+            let map: RangeMap = s.parse()?;
+            if map.validate().iter().any(MapIssue::is_error) {
+                return Err(ParseRangeMapErr::Ambiguous);
+            }
+            Ok(map)
+        }
+
+        /// Print the map as a table of source intervals, where each one maps the id `start`
+        /// to `destination`, and each id after that `scale` further on. Ids outside of every
+        /// interval are left alone.
+        pub fn to_table(&self) -> String {
+            let mut table = format!(
+                "{:>21} {:>21} {:>21} {:>21}\n",
+                "start", "end", "destination", "scale"
+            );
+            for entry in &self.sorted {
+                writeln!(
+                    table,
+                    "{:>21} {:>21} {:>21} {:>21}",
+                    entry.source_range.start,
+                    entry.source_range.end,
+                    entry.destination_start,
+                    entry.scale
+                )
+                .unwrap();
+            }
+            table
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum MapIssue {
+        /// Two entries both cover these source ids; `lookup` uses the first one.
+        Overlap {
+            first: usize,
+            second: usize,
+            sources: Range<i64>,
+        },
+        /// An entry with a length of zero or less, which never matches.
+        EmptyEntry { entry: usize, length: i64 },
+        /// Source ids between two entries that no entry covers, so they map to themselves.
+        Gap(Range<i64>),
+    }
+
+    impl MapIssue {
+        /// Whether a strict parse should refuse the map.
+        pub fn is_error(&self) -> bool {
+            match self {
+                MapIssue::Overlap { .. } => true,
+                MapIssue::EmptyEntry { length, .. } => *length < 0,
+                MapIssue::Gap(_) => false,
+            }
+        }
+    }
+
+    impl fmt::Display for MapIssue {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                MapIssue::Overlap {
+                    first,
+                    second,
+                    sources,
+                } => write!(f, "entries {first} and {second} overlap on {sources:?}"),
+                MapIssue::EmptyEntry { entry, length } => {
+                    write!(f, "entry {entry} has length {length}")
+                }
+                MapIssue::Gap(sources) => write!(f, "no entry covers {sources:?}"),
+            }
+        }
+    }
+
+    /// Sort the ranges, and merge any that overlap or touch.
+    fn merge(mut ranges: Vec<Range<i64>>) -> Vec<Range<i64>> {
+        ranges.retain(|range| !range.is_empty());
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<i64>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// The parts of `a` that are not in `b`.
+    fn subtract(a: &Range<i64>, b: &Range<i64>) -> Vec<Range<i64>> {
+        let mut parts = Vec::new();
+        if a.start < b.start {
+            parts.push(a.start..a.end.min(b.start));
+        }
+        if a.end > b.end {
+            parts.push(a.start.max(b.end)..a.end);
+        }
+        parts.retain(|part| !part.is_empty());
+        parts
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum ParseRangeMapErr {
+        Malformed,
+        /// An entry's ranges don't fit in an `i64`.
+        Overflow,
+        /// A strict parse found entries that overlap, or have a negative length.
+        Ambiguous,
     }
 
-    #[derive(Debug)]
-    pub struct ParseRangeMapErr;
+    impl From<ParseRangeMapEntryErr> for ParseRangeMapErr {
+        fn from(err: ParseRangeMapEntryErr) -> Self {
+            match err {
+                ParseRangeMapEntryErr::Malformed | ParseRangeMapEntryErr::ZeroScale => {
+                    ParseRangeMapErr::Malformed
+                }
+                ParseRangeMapEntryErr::Overflow => ParseRangeMapErr::Overflow,
+            }
+        }
+    }
+
+    enum ParseIntErrorKind {
+        Malformed,
+        Overflow,
+    }
+
+    /// Tell apart numbers that are too big for their type from anything else.
+    fn parse_int_error(err: &ParseIntError) -> ParseIntErrorKind {
+        match err.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => ParseIntErrorKind::Overflow,
+            _ => ParseIntErrorKind::Malformed,
+        }
+    }
 
     impl FromStr for RangeMap {
         type Err = ParseRangeMapErr;
@@ -161,22 +667,96 @@ mod almanac {
         fn from_str(s: &str) -> Result<RangeMap, Self::Err> {
             let entries: Vec<_> = s
                 .lines()
-                .map(|line| line.parse::<RangeMapEntry>().map_err(|_| ParseRangeMapErr))
+                .map(|line| {
+                    line.parse::<RangeMapEntry>()
+                        .map_err(ParseRangeMapErr::from)
+                })
                 .collect::<Result<Vec<_>, _>>()?;
-            Ok(RangeMap { entries })
+            Ok(RangeMap::new(entries))
+        }
+    }
+
+    /// How to read the numbers on the "seeds:" line.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum SeedMode {
+        /// Each number is a seed.
+        Individual,
+        /// The numbers are pairs of a first seed and a number of seeds.
+        Ranges,
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum Seeds {
+        Individual(Vec<i64>),
+        Ranges(Vec<Range<i64>>),
+    }
+
+    impl Seeds {
+        fn parse(s: &str, mode: SeedMode) -> Result<Seeds, ParseAlmanacErr> {
+            let numbers = s
+                .split_whitespace()
+                .map(|x| {
+                    x.parse::<i64>().map_err(|e| match parse_int_error(&e) {
+                        ParseIntErrorKind::Overflow => ParseAlmanacErr::Overflow,
+                        ParseIntErrorKind::Malformed => ParseAlmanacErr::Malformed,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match mode {
+                SeedMode::Individual => {
+                    // Every seed must be a range of one, so `i64::MAX` is out of range.
+                    if numbers.contains(&i64::MAX) {
+                        return Err(ParseAlmanacErr::Overflow);
+                    }
+                    Ok(Seeds::Individual(numbers))
+                }
+                SeedMode::Ranges => {
+                    if numbers.len() % 2 != 0 {
+                        return Err(ParseAlmanacErr::OddSeedCount);
+                    }
+                    let ranges = numbers
+                        .chunks_exact(2)
+                        .map(|pair| {
+                            let (start, length) = (pair[0], pair[1]);
+                            if length < 0 {
+                                return Err(ParseAlmanacErr::Malformed);
+                            }
+                            let end = start.checked_add(length).ok_or(ParseAlmanacErr::Overflow)?;
+                            Ok(start..end)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok(Seeds::Ranges(ranges))
+                }
+            }
+        }
+
+        /// The seeds as sorted and merged ranges, where individual seeds are ranges of one.
+        pub fn ranges(&self) -> Vec<Range<i64>> {
+            match self {
+                Seeds::Individual(seeds) => {
+                    merge(seeds.iter().map(|&seed| seed..(seed + 1)).collect())
+                }
+                Seeds::Ranges(ranges) => merge(ranges.clone()),
+            }
         }
     }
 
     pub struct Almanac {
-        /// The ids of the seeds that we need.
-        pub seeds: Vec<i64>,
+        /// The seeds that we need.
+        pub seeds: Seeds,
+
+        /// The names of the categories, in order, starting from "seed".
+        pub categories: Vec<String>,
 
         /// An ordered list of maps, which map seed -> .... -> location.
-        /// The inner levels are not named for now.
+        /// Map `i` goes from `categories[i]` to `categories[i + 1]`.
         pub maps: Vec<RangeMap>,
     }
 
     impl Almanac {
+        // These walk through every layer in turn; the puzzle uses `flatten` instead.
+        #[cfg_attr(not(test), expect(dead_code))]
         pub fn lookup(&self, seed: i64) -> i64 {
             let mut id = seed;
             for map in &self.maps {
@@ -185,104 +765,211 @@ mod almanac {
             id
         }
 
-        pub fn lookup_range(&self, seeds: Range<i64>) -> Vec<Range<i64>> {
-            let mut ranges: Vec<Range<i64>> = [seeds].to_vec();
-            for map in &self.maps {
-                // Replace the ranges with the result of applying this layer of mappings.
-                ranges = ranges.iter().flat_map(|r| map.lookup_range(r)).collect();
-            }
-            ranges
+        /// Combine every layer of the almanac into a single seed -> location map.
+        pub fn flatten(&self) -> RangeMap {
+            compose_all(&self.maps)
         }
-    }
 
-    #[derive(Debug)]
-    pub struct ParseAlmanacErr;
+        /// Combine the layers between two named categories into a single map, e.g. from
+        /// "soil" to "humidity". Returns `None` if either category is unknown, or if `to`
+        /// comes before `from`; use `RangeMap::preimage` to go backwards.
+        pub fn map_between(&self, from: &str, to: &str) -> Option<RangeMap> {
+            let index = |name| self.categories.iter().position(|category| category == name);
+            let (i_from, i_to) = (index(from)?, index(to)?);
+            (i_from <= i_to).then(|| compose_all(&self.maps[i_from..i_to]))
+        }
 
-    impl FromStr for Almanac {
-        type Err = ParseAlmanacErr;
+        /// Parse an almanac, refusing it if any of its maps have entries that overlap, or
+        /// entries of negative length. See `RangeMap::validate`.
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn from_str_strict(s: &str) -> Result<Almanac, ParseAlmanacErr> {
+            let almanac: Almanac = s.parse()?;
+            for (from, map) in almanac.categories.iter().zip(&almanac.maps) {
+                let issues: Vec<MapIssue> = map
+                    .validate()
+                    .into_iter()
+                    .filter(MapIssue::is_error)
+                    .collect();
+                if !issues.is_empty() {
+                    return Err(ParseAlmanacErr::InvalidMap {
+                        from: from.clone(),
+                        issues,
+                    });
+                }
+            }
+            Ok(almanac)
+        }
 
-        fn from_str(s: &str) -> Result<Almanac, Self::Err> {
-            let groups: Vec<_> = s.trim().split("\n\n").collect();
+        /// Return the locations of all of the seeds, as sorted and merged ranges.
+        pub fn seed_locations(&self) -> Vec<Stride> {
+            let seeds: Vec<Stride> = self.seeds.ranges().into_iter().map(Stride::from).collect();
+            self.flatten().lookup_ranges(&seeds)
+        }
 
-            // Very unexpected if this assert fails! Please let us know...
-            assert!(groups.len() == 8);
+        /// Parse an almanac, reading the seeds as given by `mode`.
+        pub fn parse_with(s: &str, mode: SeedMode) -> Result<Almanac, ParseAlmanacErr> {
+            let groups: Vec<_> = s.trim().split("\n\n").collect();
 
-            let seeds = groups[0]
-                .trim()
-                .replace("seeds: ", "")
-                .split_whitespace()
-                .map(|x| x.parse::<i64>().map_err(|_| ParseAlmanacErr))
-                .collect::<Result<Vec<_>, _>>()?;
+            let seeds = Seeds::parse(
+                groups[0]
+                    .trim()
+                    .strip_prefix("seeds:")
+                    .ok_or(ParseAlmanacErr::Malformed)?,
+                mode,
+            )?;
 
-            // Absorb all maps.
-            let maps = (1..groups.len())
-                .map(|i| {
-                    let group = groups[i];
+            // Absorb all maps, checking that each one starts where the last one ended.
+            let mut categories = vec![String::from("seed")];
+            let mut maps = Vec::new();
+            for group in &groups[1..] {
+                let (map_name_line, rest) =
+                    group.split_once('\n').ok_or(ParseAlmanacErr::Malformed)?;
+                let (from, to) = map_name_line
+                    .trim()
+                    .strip_suffix(" map:")
+                    .and_then(|name| name.split_once("-to-"))
+                    .ok_or(ParseAlmanacErr::Malformed)?;
 
-                    // Not using map name for now.
-                    let (_map_name_line, rest) = group.split_once('\n').ok_or(ParseAlmanacErr)?;
-                    rest.parse::<RangeMap>().map_err(|_| ParseAlmanacErr)
-                })
-                .collect::<Result<Vec<_>, _>>()?;
+                let expected = categories.last().unwrap();
+                if from != expected {
+                    return Err(ParseAlmanacErr::BrokenChain {
+                        expected: expected.clone(),
+                        found: from.to_string(),
+                    });
+                }
+                if categories.iter().any(|category| category == to) {
+                    return Err(ParseAlmanacErr::RepeatedCategory(to.to_string()));
+                }
+                categories.push(to.to_string());
+                maps.push(rest.parse::<RangeMap>().map_err(|e| match e {
+                    ParseRangeMapErr::Overflow => ParseAlmanacErr::Overflow,
+                    _ => ParseAlmanacErr::Malformed,
+                })?);
+            }
 
-            Ok(Almanac { seeds, maps })
+            Ok(Almanac {
+                seeds,
+                categories,
+                maps,
+            })
         }
-    }
-}
 
-fn part1(input: &str) -> String {
-    let almanac: Almanac = input.parse().unwrap();
+        /// Return every seed that ends up at a location in `locations`, as sorted and merged
+        /// ranges.
+        pub fn preimage(&self, locations: &Range<i64>) -> Vec<Range<i64>> {
+            self.flatten().preimage(locations)
+        }
 
-    let answer = almanac
-        .seeds
-        .iter()
-        .map(|&seed| almanac.lookup(seed))
-        .min()
-        .unwrap();
+        #[cfg_attr(not(test), expect(dead_code))]
+        pub fn lookup_range(&self, seeds: Range<i64>) -> Vec<Stride> {
+            let mut ids = RangeMap::identity().lookup_range(&seeds);
+            for map in &self.maps {
+                // Replace the ids with the result of applying this layer of mappings.
+                ids = map.lookup_ranges(&ids);
+            }
+            ids
+        }
+    }
 
-    answer.to_string()
-}
+    fn compose_all(maps: &[RangeMap]) -> RangeMap {
+        maps.iter()
+            .fold(RangeMap::identity(), |map, next| map.compose(next))
+    }
 
-fn part2(input: &str) -> String {
-    // Slight hack -- we are going to reinterpret our almanac vector of seeds as pairs
-    // denoting ranges, rather than alter the parsing.
-    let almanac: Almanac = input.parse().unwrap();
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum ParseAlmanacErr {
+        Malformed,
+        /// A number, or the range of a map entry, doesn't fit in an `i64`.
+        Overflow,
+        /// A map doesn't start from the category where the previous one ended.
+        BrokenChain {
+            expected: String,
+            found: String,
+        },
+        /// Seed ranges come in pairs, so there must be an even number of numbers.
+        OddSeedCount,
+        /// A category appears more than once, so we couldn't tell which one is meant.
+        RepeatedCategory(String),
+        /// A strict parse found problems with the map that starts at this category.
+        InvalidMap {
+            from: String,
+            issues: Vec<MapIssue>,
+        },
+    }
 
-    assert!(
-        almanac.seeds.len() % 2 == 0,
-        "Should have an even number of seed entries."
-    );
+    impl FromStr for Almanac {
+        type Err = ParseAlmanacErr;
 
-    let n = almanac.seeds.len() / 2;
-    let answer = (0..n)
-        .map(|i| {
-            let seed_start = almanac.seeds[2 * i];
-            let seed_range_len = almanac.seeds[2 * i + 1];
+        fn from_str(s: &str) -> Result<Almanac, Self::Err> {
+            Almanac::parse_with(s, SeedMode::Individual)
+        }
+    }
+}
 
-            let seed_range = seed_start..(seed_start + seed_range_len);
+// Find the lowest location of any of the seeds, reading the seeds as given by `mode`.
+fn lowest_location(input: &str, mode: SeedMode) -> i64 {
+    let almanac = Almanac::parse_with(input, mode).unwrap();
+    // NOTE: The locations are sorted, so the first one is the lowest.
+    almanac.seed_locations().first().unwrap().start()
+}
 
-            almanac
-                .lookup_range(seed_range)
-                .iter()
-                .map(|r| r.start)
-                .min()
-                .unwrap()
-        })
-        .min()
-        .unwrap();
+fn part1(input: &str) -> String {
+    lowest_location(input, SeedMode::Individual).to_string()
+}
 
-    answer.to_string()
+fn part2(input: &str) -> String {
+    lowest_location(input, SeedMode::Ranges).to_string()
 }
 
 fn main() {
     let input = get_input();
     println!("Part1: {}", part1(input));
     println!("Part2: {}", part2(input));
+
+    // Optionally show the whole almanac as a single seed -> location map.
+    match env::args().nth(1).as_deref() {
+        None => {}
+        Some("table") => {
+            // Optionally, only show the layers between two categories.
+            let almanac: Almanac = input.parse().unwrap();
+            let from = env::args().nth(2);
+            let to = env::args().nth(3);
+            let from = from
+                .as_deref()
+                .unwrap_or(almanac.categories.first().unwrap());
+            let to = to.as_deref().unwrap_or(almanac.categories.last().unwrap());
+            let map = almanac
+                .map_between(from, to)
+                .unwrap_or_else(|| panic!("No map from '{from}' to '{to}'."));
+            print!("{}", map.to_table());
+        }
+        Some("below") => {
+            // Which seeds end up at a location below the given one?
+            let almanac: Almanac = input.parse().unwrap();
+            let location: i64 = env::args().nth(2).unwrap().parse().unwrap();
+            for seeds in almanac.preimage(&(0..location)) {
+                println!("{seeds:?}");
+            }
+        }
+        Some("check") => {
+            let almanac: Almanac = input.parse().unwrap();
+            let stages = almanac.categories.windows(2).zip(&almanac.maps);
+            for (names, map) in stages {
+                for issue in map.validate() {
+                    println!("{}-to-{} map: {issue}", names[0], names[1]);
+                }
+            }
+        }
+        Some(x) => panic!("Unknown argument '{x}'; expected 'table', 'below' or 'check'."),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::almanac::{Almanac, RangeMap, RangeMapEntry};
+    use crate::almanac::{
+        Almanac, MapIssue, ParseAlmanacErr, ParseRangeMapEntryErr, ParseRangeMapErr, RangeMap,
+        RangeMapEntry, SeedMode, Seeds, Stride,
+    };
     use crate::{part1, part2};
 
     const EXAMPLE: &str = "
@@ -364,7 +1051,329 @@ humidity-to-location map:
     #[test]
     fn test_parse_almanac() {
         let almanac: Almanac = EXAMPLE.parse().unwrap();
-        assert_eq!(almanac.seeds, [79, 14, 55, 13]);
+        assert_eq!(almanac.seeds, Seeds::Individual(vec![79, 14, 55, 13]));
         assert_eq!(almanac.maps.len(), 7);
+        assert_eq!(almanac.categories.len(), 8);
+        assert_eq!(almanac.categories[1], "soil");
+        assert_eq!(almanac.categories[7], "location");
+    }
+
+    #[test]
+    fn test_compose() {
+        let first: RangeMap = "50 98 2\n52 50 48".parse().unwrap();
+        let second: RangeMap = "0 15 37\n37 52 2\n39 0 15".parse().unwrap();
+        let composed = first.compose(&second);
+        for id in -10..120 {
+            assert_eq!(
+                composed.lookup(id),
+                second.lookup(first.lookup(id)),
+                "id {id}"
+            );
+        }
+
+        // Where entries overlap, the first one wins.
+        let overlapping: RangeMap = "100 0 10\n200 5 10".parse().unwrap();
+        assert_eq!(overlapping.lookup(5), 105);
+        assert_eq!(overlapping.lookup(12), 207);
+        assert_eq!(overlapping.lookup(15), 15);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let almanac: Almanac = EXAMPLE.parse().unwrap();
+        let map = almanac.flatten();
+        for seed in -10..120 {
+            assert_eq!(map.lookup(seed), almanac.lookup(seed), "seed {seed}");
+        }
+
+        let mut from_layers: Vec<i64> = almanac
+            .lookup_range(79..93)
+            .iter()
+            .flat_map(Stride::iter)
+            .collect();
+        let mut from_map: Vec<i64> = map
+            .lookup_range(&(79..93))
+            .iter()
+            .flat_map(Stride::iter)
+            .collect();
+        from_layers.sort_unstable();
+        from_map.sort_unstable();
+        assert_eq!(from_layers, from_map);
+
+        let identity: RangeMap = "5 5 10".parse().unwrap();
+        assert_eq!(identity.to_table().lines().count(), 1);
+        let table = RangeMap::identity()
+            .compose(&"52 50 48".parse().unwrap())
+            .to_table();
+        assert_eq!(
+            table
+                .lines()
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            ["50", "98", "52", "1"]
+        );
+    }
+
+    #[test]
+    fn test_preimage() {
+        let entry: RangeMapEntry = "50 98 2".parse().unwrap();
+        assert_eq!(entry.preimage(&(0..51)), Some(98..99));
+        assert_eq!(entry.preimage(&(52..60)), None);
+
+        // Both 50 and 98 end up at 50, since 50 isn't covered by this map.
+        let map: RangeMap = "50 98 2".parse().unwrap();
+        assert_eq!(map.preimage_of(50), [50, 98]);
+        assert_eq!(map.preimage_of(98), Vec::<i64>::new());
+        assert_eq!(map.preimage(&(49..52)), [49..52, 98..100]);
+
+        let almanac: Almanac = EXAMPLE.parse().unwrap();
+        let seeds = almanac.preimage(&(0..47));
+        for seed in 0..120 {
+            let in_preimage = seeds.iter().any(|range| range.contains(&seed));
+            assert_eq!(in_preimage, almanac.lookup(seed) < 47, "seed {seed}");
+        }
+        assert!(seeds.iter().any(|range| range.contains(&82)));
+    }
+
+    #[test]
+    fn test_categories() {
+        let almanac: Almanac = EXAMPLE.parse().unwrap();
+        // Seed 79 has soil 81, and humidity 78.
+        let map = almanac.map_between("soil", "humidity").unwrap();
+        assert_eq!(map.lookup(81), 78);
+        assert_eq!(almanac.map_between("seed", "seed").unwrap().lookup(79), 79);
+        assert!(almanac.map_between("humidity", "soil").is_none());
+        assert!(almanac.map_between("soil", "weather").is_none());
+
+        // Any number of stages is fine, as long as they form a chain.
+        let short: Almanac = "seeds: 1 2\n\nseed-to-soil map:\n10 0 5".parse().unwrap();
+        assert_eq!(short.categories, ["seed", "soil"]);
+        assert_eq!(short.flatten().lookup(2), 12);
+        let none: Almanac = "seeds: 1 2".parse().unwrap();
+        assert_eq!(none.flatten().lookup(2), 2);
+
+        let broken = "seeds: 1\n\nseed-to-soil map:\n1 2 3\n\nwater-to-light map:\n1 2 3";
+        assert_eq!(
+            broken.parse::<Almanac>().err(),
+            Some(ParseAlmanacErr::BrokenChain {
+                expected: "soil".to_string(),
+                found: "water".to_string()
+            })
+        );
+        let repeated = "seeds: 1\n\nseed-to-soil map:\n1 2 3\n\nsoil-to-seed map:\n1 2 3";
+        assert_eq!(
+            repeated.parse::<Almanac>().err(),
+            Some(ParseAlmanacErr::RepeatedCategory("seed".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let map: RangeMap = "50 98 2\n52 50 48".parse().unwrap();
+        assert_eq!(map.validate(), []);
+
+        let map: RangeMap = "0 10 5\n100 20 10\n200 25 10\n1 1 0\n2 2 -3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            map.validate(),
+            [
+                MapIssue::Overlap {
+                    first: 1,
+                    second: 2,
+                    sources: 25..30
+                },
+                MapIssue::EmptyEntry {
+                    entry: 3,
+                    length: 0
+                },
+                MapIssue::EmptyEntry {
+                    entry: 4,
+                    length: -3
+                },
+                MapIssue::Gap(15..20),
+            ]
+        );
+        assert_eq!(
+            map.validate()[0].to_string(),
+            "entries 1 and 2 overlap on 25..30"
+        );
+
+        // Zero-length entries and gaps are fine, even in strict mode.
+        assert!(RangeMap::from_str_strict("0 10 5\n1 1 0\n5 20 1").is_ok());
+        assert!(RangeMap::from_str_strict("0 10 5\n1 12 1").is_err());
+        assert!(RangeMap::from_str_strict("0 10 -1").is_err());
+
+        assert!(Almanac::from_str_strict(EXAMPLE).is_ok());
+        let overlapping =
+            "seeds: 1\n\nseed-to-soil map:\n1 2 3\n\nsoil-to-water map:\n1 2 3\n5 3 1";
+        assert!(overlapping.parse::<Almanac>().is_ok());
+        assert!(matches!(
+            Almanac::from_str_strict(overlapping),
+            Err(ParseAlmanacErr::InvalidMap { from, issues }) if from == "soil" && issues.len() == 1
+        ));
+    }
+
+    #[test]
+    #[expect(clippy::single_range_in_vec_init)]
+    fn test_normalised_ranges() {
+        // The pieces of the range are shuffled by the map, but fit back together.
+        let map: RangeMap = "50 98 2\n52 50 48".parse().unwrap();
+        assert_eq!(map.lookup_range(&(0..200)), [0..200]);
+        assert_eq!(map.lookup_range(&(90..100)), [50..52, 92..100]);
+        assert_eq!(
+            map.lookup_ranges(&[97..99, 0..10, 5..20].map(Stride::from)),
+            [0..20, 50..51, 99..100]
+        );
+
+        let almanac: Almanac = EXAMPLE.parse().unwrap();
+        let ranges = almanac.lookup_range(0..100);
+        assert!(ranges
+            .windows(2)
+            .all(|pair| pair[0].end() < pair[1].start()));
+        assert_eq!(ranges, almanac.flatten().lookup_range(&(0..100)));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let overflow = |s: &str| s.parse::<RangeMapEntry>().err();
+        assert_eq!(
+            overflow("0 9223372036854775807 1"),
+            Some(ParseRangeMapEntryErr::Overflow)
+        );
+        assert_eq!(
+            overflow("9223372036854775807 0 1"),
+            Some(ParseRangeMapEntryErr::Overflow)
+        );
+        assert_eq!(
+            overflow("99999999999999999999 0 1"),
+            Some(ParseRangeMapEntryErr::Overflow)
+        );
+        assert_eq!(overflow("1 2 x"), Some(ParseRangeMapEntryErr::Malformed));
+
+        // The offset from one end of `i64` to the other doesn't fit in an `i64`.
+        let there: RangeMap = "9223372036854775806 -9223372036854775808 1"
+            .parse()
+            .unwrap();
+        let back: RangeMap = "-9223372036854775808 9223372036854775806 1"
+            .parse()
+            .unwrap();
+        assert_eq!(there.lookup(i64::MIN), i64::MAX - 1);
+        assert_eq!(
+            there.lookup_range(&(i64::MIN..(i64::MIN + 2))),
+            [(i64::MIN + 1)..(i64::MIN + 2), (i64::MAX - 1)..i64::MAX]
+        );
+        assert_eq!(there.compose(&back).lookup(i64::MIN), i64::MIN);
+        assert_eq!(there.preimage_of(i64::MAX - 1), [i64::MIN, i64::MAX - 1]);
+        assert_eq!(there.preimage_of(i64::MAX), [i64::MAX]);
+
+        assert_eq!(
+            "0 9223372036854775807 1".parse::<RangeMap>().err(),
+            Some(ParseRangeMapErr::Overflow)
+        );
+        let almanac = "seeds: 1\n\nseed-to-soil map:\n0 9223372036854775807 1";
+        assert_eq!(
+            almanac.parse::<Almanac>().err(),
+            Some(ParseAlmanacErr::Overflow)
+        );
+        let almanac = "seeds: 99999999999999999999";
+        assert_eq!(
+            almanac.parse::<Almanac>().err(),
+            Some(ParseAlmanacErr::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_seeds() {
+        let almanac = Almanac::parse_with(EXAMPLE, SeedMode::Ranges).unwrap();
+        assert_eq!(almanac.seeds, Seeds::Ranges(vec![79..93, 55..68]));
+        assert_eq!(almanac.seeds.ranges(), [55..68, 79..93]);
+        assert_eq!(Seeds::Individual(vec![3, 1, 2, 7]).ranges(), [1..4, 7..8]);
+
+        let odd = "seeds: 1 2 3";
+        assert!(odd.parse::<Almanac>().is_ok());
+        assert_eq!(
+            Almanac::parse_with(odd, SeedMode::Ranges).err(),
+            Some(ParseAlmanacErr::OddSeedCount)
+        );
+        assert_eq!(
+            Almanac::parse_with("seeds: 9223372036854775800 100", SeedMode::Ranges).err(),
+            Some(ParseAlmanacErr::Overflow)
+        );
+        assert_eq!(
+            Almanac::parse_with("seeds: 1 -1", SeedMode::Ranges).err(),
+            Some(ParseAlmanacErr::Malformed)
+        );
+
+        // Seeds 79, 14, 55 and 13 have locations 82, 43, 86 and 35.
+        let almanac: Almanac = EXAMPLE.parse().unwrap();
+        assert_eq!(almanac.seed_locations(), [35..36, 43..44, 82..83, 86..87]);
+    }
+
+    #[test]
+    fn test_affine_entries() {
+        let entry: RangeMapEntry = "10 3 4 -2".parse().unwrap();
+        let mapped: Vec<_> = (2..8).map(|id| entry.lookup(id)).collect();
+        assert_eq!(mapped, [None, Some(10), Some(8), Some(6), Some(4), None]);
+
+        let error = |s: &str| s.parse::<RangeMapEntry>().err();
+        assert_eq!(error("1 2 3 0"), Some(ParseRangeMapEntryErr::ZeroScale));
+        assert_eq!(error("1 2 3 4 5"), Some(ParseRangeMapEntryErr::Malformed));
+        assert_eq!(
+            error("0 0 10 2000000000000000000"),
+            Some(ParseRangeMapEntryErr::Overflow)
+        );
+        assert_eq!(
+            error("0 0 10 -2000000000000000000"),
+            Some(ParseRangeMapEntryErr::Overflow)
+        );
+
+        let map: RangeMap = "0 0 5 2".parse().unwrap();
+        let strides = map.lookup_range(&(0..5));
+        assert_eq!(strides.len(), 1);
+        assert_eq!(strides[0].iter().collect::<Vec<_>>(), [0, 2, 4, 6, 8]);
+        assert!(strides[0].contains(6) && !strides[0].contains(7));
+    }
+
+    #[test]
+    fn test_affine_exact() {
+        let first: RangeMap = "10 3 4 -2\n100 20 10 3\n50 40 5".parse().unwrap();
+        let second: RangeMap = "0 5 6 -1\n200 100 15 2\n-5 44 3 -3".parse().unwrap();
+        let composed = first.compose(&second);
+        for id in -10..80 {
+            assert_eq!(
+                composed.lookup(id),
+                second.lookup(first.lookup(id)),
+                "id {id}"
+            );
+        }
+
+        // Range lookups give exactly the ids that the points map to.
+        for map in [&first, &second, &composed] {
+            for source in [0..10, 15..35, -3..60, 44..45] {
+                let mut expected: Vec<i64> = source.clone().map(|id| map.lookup(id)).collect();
+                expected.sort_unstable();
+                expected.dedup();
+                let mut found: Vec<i64> = map
+                    .lookup_range(&source)
+                    .iter()
+                    .flat_map(Stride::iter)
+                    .collect();
+                found.sort_unstable();
+                found.dedup();
+                assert_eq!(found, expected, "source {source:?}");
+            }
+
+            // ... and so do reverse lookups.
+            for target in [0..10, 100..140, -20..5] {
+                let sources = map.preimage(&target);
+                for id in -10..80 {
+                    let in_preimage = sources.iter().any(|range| range.contains(&id));
+                    assert_eq!(in_preimage, target.contains(&map.lookup(id)), "id {id}");
+                }
+            }
+        }
     }
 }
@@ -244,10 +244,10 @@ impl SimpleCycle {
                         state_second: cycle.state_first + length / 2,
                     })
                 } else {
-                    Err(format!("Can't simplify cycle: {cycle:?}",))
+                    Err(format!("Can't simplify cycle: {cycle:?}"))
                 }
             }
-            _ => Err(format!("Can't simplify cycle: {cycle:?}",)),
+            _ => Err(format!("Can't simplify cycle: {cycle:?}")),
         }
     }
 }